@@ -0,0 +1,168 @@
+// Headless terrain generation, for CI and designers without a GPU or window.
+//
+// Usage: hybrid-terrain [--seed=<seed>] [--size=<width>x<height>] [--profile=bumpy|downhill]
+//                       [--heightmap=<image>] [--height-scale=<height of white>] [--course=<ron file>] [--res=<tessellation>]
+//                       [--obj=<path>] [--gltf=<path>] [--save-course=<path>]
+//        hybrid-terrain --replay=<recording> [--golden=<trajectory>] [--save-golden=<path>]
+extern crate hybrid;
+extern crate rand;
+
+use hybrid::hybrid::{ControlSurface, Downhill, Profile, CHUNK_SCALE};
+use hybrid::hybrid::export;
+use hybrid::hybrid::replay::{self, Recording, Trajectory};
+use hybrid::hybrid::terrain::{self, TerrainSource};
+
+use rand::{thread_rng, Rng};
+
+use std::env;
+use std::fs;
+use std::process;
+use std::time::Instant;
+
+const SLOPE_BINS: usize = 9;
+
+fn fail(message: String) -> ! {
+    eprintln!("{}", message);
+    process::exit(1)
+}
+
+// Replays a recorded game without a window, checking the balls follow the same paths
+fn check_replay(path: &str, golden: Option<String>, save_golden: Option<String>) {
+    let recording = Recording::load(path)
+        .unwrap_or_else(|err| fail(format!("Failed to load recording {}: {}", path, err)));
+    let steps: usize = recording.players.iter().map(|player| player.inputs.len()).sum();
+    println!("Replaying {} players for {} steps on seed {}", recording.players.len(), steps, recording.terrain.seed);
+
+    let start = Instant::now();
+    let trajectory = replay::replay(&recording);
+    println!("Replayed in {:?}", start.elapsed());
+
+    trajectory.compare(&recording.trajectory(), replay::TOLERANCE)
+        .unwrap_or_else(|err| fail(format!("Replay differs from the recording: {}", err)));
+    println!("Replay matches the recording");
+
+    if let Some(path) = golden {
+        let golden = Trajectory::load(&path)
+            .unwrap_or_else(|err| fail(format!("Failed to load golden trajectory {}: {}", path, err)));
+        trajectory.compare(&golden, replay::TOLERANCE)
+            .unwrap_or_else(|err| fail(format!("Replay differs from golden trajectory {}: {}", path, err)));
+        println!("Replay matches golden trajectory {}", path);
+    };
+    if let Some(path) = save_golden {
+        trajectory.save(&path).unwrap_or_else(|err| fail(format!("Failed to write {}: {}", path, err)));
+        println!("Wrote {}", path);
+    };
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let flag_value = |name: &str| args.iter()
+        .find(|arg| arg.starts_with(name) && arg[name.len()..].starts_with("="))
+        .map(|arg| arg[(name.len() + 1)..].to_string());
+
+    if let Some(path) = flag_value("--replay") {
+        check_replay(&path, flag_value("--golden"), flag_value("--save-golden"));
+        return
+    };
+
+    let seed = flag_value("--seed")
+        .map(|seed| seed.parse().unwrap_or_else(|_| fail(format!("Invalid seed: {}", seed))))
+        .unwrap_or_else(|| thread_rng().gen());
+    let size = flag_value("--size").map(|size| {
+        let dimensions: Vec<usize> = size.split('x').filter_map(|n| n.parse().ok()).collect();
+        match dimensions.as_slice() {
+            [width, height] if *width >= 2 && *height >= 2 => (*width, *height),
+            _ => fail(format!("Invalid size, expected <width>x<height> of at least 2x2: {}", size))
+        }
+    });
+    let profile = match flag_value("--profile").as_ref().map(|profile| profile.as_str()) {
+        None | Some("bumpy") => Profile::Bumpy,
+        Some("downhill") => Profile::Downhill(Downhill::default()),
+        Some(profile) => fail(format!("Unknown profile: {}", profile))
+    };
+    let height_scale = flag_value("--height-scale")
+        .map(|scale| scale.parse().unwrap_or_else(|_| fail(format!("Invalid height scale: {}", scale))))
+        .unwrap_or(terrain::HEIGHT_SCALE);
+    let res = flag_value("--res")
+        .map(|res| match res.parse::<i32>() {
+            Ok(n) if n > 0 => n,
+            _ => fail(format!("Invalid resolution, expected a positive number: {}", res))
+        })
+        .unwrap_or(8);
+
+    // Build the control surface
+    let start = Instant::now();
+    let cs = match (flag_value("--heightmap"), flag_value("--course")) {
+        (Some(path), _) => terrain::load_heightmap(&path, size, height_scale)
+            .unwrap_or_else(|err| fail(format!("Failed to load heightmap {}: {}", path, err))),
+        (None, Some(path)) => {
+            let ron = fs::read_to_string(&path)
+                .unwrap_or_else(|err| fail(format!("Failed to read course {}: {}", path, err)));
+            ControlSurface::from_ron(&ron)
+                .unwrap_or_else(|err| fail(format!("Failed to parse course {}: {}", path, err)))
+        },
+        (None, None) => {
+            println!("Seed: {}", seed);
+            let (width, height) = size.unwrap_or((64, 64));
+            ControlSurface::from_profile(width, height, seed, &profile)
+        }
+    };
+    let surface_time = start.elapsed();
+    println!("Control planes: {} x {} ({:?})", cs.width(), cs.height(), surface_time);
+
+    // Generate the chunks exactly as the game does
+    let start = Instant::now();
+    let mut coords = Vec::new();
+    for i in 0..(cs.width() as i32 - 1) {
+        for j in 0..(cs.height() as i32 - 1) {
+            coords.push((i, j))
+        }
+    };
+    let source = TerrainSource::Surface(cs.clone());
+    let chunks = terrain::generate_chunks(&source, &coords, &[]);
+    let chunk_time = start.elapsed();
+
+    let render_triangles: usize = chunks.iter().map(|geometry| geometry.vertices.len() / 3).sum();
+    let collision_triangles: usize = chunks.iter().map(|geometry| geometry.chunk.collision_mesh.indices().len()).sum();
+    println!("Chunks: {} ({:?})", chunks.len(), chunk_time);
+    println!("Triangles: {} render, {} collision", render_triangles, collision_triangles);
+
+    // Sample the surface for statistics
+    let start = Instant::now();
+    let mesh = export::tessellate(&cs, res);
+    println!("Tessellated at {} * {} per patch: {} vertices, {} triangles ({:?})",
+             res, res, mesh.vertices.len(), mesh.indices.len(), start.elapsed());
+
+    let (min, max) = mesh.vertices.iter().fold((::std::f32::MAX, ::std::f32::MIN), |(min, max), vertex| {
+        (min.min(vertex.position.y), max.max(vertex.position.y))
+    });
+    println!("Height: {:.2} to {:.2} ({:.2} world units, {:.2} control planes)", min, max, max - min, (max - min) / CHUNK_SCALE);
+
+    // Slope angle from horizontal, in 10 degree bins
+    let mut histogram = [0usize; SLOPE_BINS];
+    for vertex in mesh.vertices.iter() {
+        let degrees = vertex.normal.y.max(-1.0).min(1.0).acos().to_degrees();
+        let bin = ((degrees / 90.0 * SLOPE_BINS as f32) as usize).min(SLOPE_BINS - 1);
+        histogram[bin] += 1;
+    };
+    println!("Slope:");
+    for (bin, count) in histogram.iter().enumerate() {
+        let fraction = *count as f32 / mesh.vertices.len() as f32;
+        println!("  {:>2}-{:>2} deg: {:>6.2}% {}", bin * 10, (bin + 1) * 10, fraction * 100.0, "#".repeat((fraction * 50.0).round() as usize));
+    };
+
+    // Optional output files
+    if let Some(path) = flag_value("--obj") {
+        export::write_obj(&cs, res, &path).unwrap_or_else(|err| fail(format!("Failed to write {}: {}", path, err)));
+        println!("Wrote {}", path);
+    };
+    if let Some(path) = flag_value("--gltf") {
+        export::write_gltf(&cs, res, &path).unwrap_or_else(|err| fail(format!("Failed to write {}: {}", path, err)));
+        println!("Wrote {}", path);
+    };
+    if let Some(path) = flag_value("--save-course") {
+        let ron = cs.to_ron().unwrap_or_else(|err| fail(format!("Failed to serialize course: {}", err)));
+        fs::write(&path, ron).unwrap_or_else(|err| fail(format!("Failed to write {}: {}", path, err)));
+        println!("Wrote {}", path);
+    };
+}
@@ -0,0 +1,251 @@
+// The ball component, and how player input moves it for one physics step.
+// Both the BallSystem and headless replays step balls through Ball::tick, so they move identically.
+
+use amethyst::ecs::prelude::*;
+use glm;
+
+use std::f32::consts::*;
+
+use super::physics::{self, BallParams, BallState, Contact, TIMESTEP};
+use super::rumble::{self, ImpactKind};
+
+// Accelerations at full strength, in world units per second squared
+const PUSH: f32 = 12.0;
+const LEAN: f32 = 4.0;
+const BRAKE: f32 = 8.0;
+// Radians per second at full steering
+const TURN_RATE: f32 = 2.0;
+
+/// The controls for a single physics step, taken from a PlayerInput or a recording
+#[derive(Clone, Copy, Default, PartialEq, Debug, Serialize, Deserialize)]
+pub struct TickInput {
+    pub steer: f32,
+    pub lean: f32,
+    /// x East and y South, towards -z
+    pub push: [f32; 2],
+    pub brake: bool,
+    pub jump: bool,
+    pub reset: bool
+}
+
+/// The direction a ball faces along the surface, turned by its rotation, and the direction it faces unturned.
+/// Both are unit length. Unturned, a ball faces along the slope, or away from the camera on flat ground.
+pub fn heading(normal: &glm::Vec3, rotation: f32) -> (glm::Vec3, glm::Vec3) {
+    let up = glm::vec3(0.0, 1.0, 0.0);
+    let across = up.cross(normal);
+    let angle = if glm::length(&across) > 1e-6 {
+        glm::rotate_vec3(&glm::normalize(&across), -(0.5 * PI), normal)
+    } else {
+        glm::vec3(0.0, 0.0, -1.0)
+    };
+    let rotation = glm::quat_angle_axis(rotation, normal);
+    (glm::quat_cross_vec(&rotation, &angle), angle)
+}
+
+/// A ball rolling over the terrain, simulated by the BallSystem
+pub struct Ball {
+    /// The last two physics steps, the ball is drawn between them
+    pub previous: BallState,
+    pub current: BallState,
+    /// The surface under the ball at the last step
+    pub contact: Option<Contact>,
+    /// Heading around the surface normal, in radians
+    pub rotation: f32,
+    /// Where the ball goes back to when reset
+    pub spawn: glm::Vec3
+}
+
+impl Component for Ball {
+    type Storage = DenseVecStorage<Self>;
+}
+
+impl Ball {
+    pub fn new(position: glm::Vec3) -> Self {
+        let state = BallState::new(position);
+        Ball {
+            previous: state,
+            current: state,
+            contact: None,
+            rotation: 0.0,
+            spawn: position
+        }
+    }
+
+    pub fn reset(&mut self) {
+        *self = Ball::new(self.spawn);
+    }
+
+    /// Advances the ball by one physics step. The contact function finds the surface under a position.
+    /// Returns how hard the ball hit the terrain, if that was an impact.
+    pub fn tick<F>(&mut self, input: &TickInput, params: &BallParams, contact: F) -> Option<(ImpactKind, f32)>
+    where
+        F: FnOnce(&glm::Vec3) -> Option<Contact>
+    {
+        if input.reset {
+            self.reset();
+        };
+
+        self.rotation += input.steer * TURN_RATE * TIMESTEP;
+        self.rotation = if self.rotation >= 2.0 * PI { self.rotation - 2.0 * PI } else { self.rotation };
+        self.rotation = if self.rotation <  0.0 * PI { self.rotation + 2.0 * PI } else { self.rotation };
+
+        let airborne = !self.contact.map_or(false, |contact| contact.touching(&self.current, params));
+        self.previous = self.current;
+        self.contact = contact(&self.current.position);
+
+        let mut thrust = glm::vec3(input.push[0], 0.0, -input.push[1]) * PUSH;
+        if let Some(contact) = self.contact {
+            thrust += heading(&contact.normal, self.rotation).0 * input.lean * LEAN;
+
+            // Braking digs in against the motion along the surface, but never reverses it
+            let velocity = self.current.velocity;
+            let along = velocity - contact.normal * glm::dot(&velocity, &contact.normal);
+            let speed = glm::length(&along);
+            if input.brake && speed > 0.0 {
+                thrust -= along * (BRAKE.min(speed / TIMESTEP) / speed);
+            }
+        };
+
+        if input.jump {
+            physics::jump(&mut self.current, params, self.contact.as_ref());
+        };
+        let impact = physics::step(&mut self.current, params, self.contact.as_ref(), thrust, TIMESTEP);
+
+        match (impact, self.contact) {
+            (Some(speed), Some(contact)) =>
+                rumble::classify_impact(speed, &contact.normal, airborne).map(|kind| (kind, speed)),
+            _ => None
+        }
+    }
+
+    /// Advances the ball by a frame's physics steps, calling on_step after each step with the step's
+    /// number in the frame, the input it took and its impact. Jumping and resetting only happen on the
+    /// first step of a frame, so they are cleared from the input once there has been one.
+    pub fn frame<F, S>(&mut self, input: &mut TickInput, steps: u32, params: &BallParams, mut contact: F, mut on_step: S)
+    where
+        F: FnMut(&glm::Vec3) -> Option<Contact>,
+        S: FnMut(u32, &Ball, &TickInput, Option<(ImpactKind, f32)>)
+    {
+        for step in 0..steps {
+            let impact = self.tick(input, params, &mut contact);
+            on_step(step, self, input, impact);
+
+            input.jump = false;
+            input.reset = false;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::physics::FixedStep;
+
+    // Frame rates the ball is rolled at, physics should not care which one the game runs at
+    const ROLL_FRAME_RATES: [f32; 2] = [30.0, 144.0];
+    // Largest distance allowed between where the ball ends up at each frame rate
+    const ROLL_TOLERANCE: f32 = 0.01;
+
+    // An endless plane through the origin
+    fn plane(normal: glm::Vec3) -> impl Fn(&glm::Vec3) -> Option<Contact> {
+        let normal = normal.normalize();
+        move |position| Some(Contact { point: position - normal * glm::dot(position, &normal), normal })
+    }
+
+    // Runs the ball for a number of seconds of frames at the given frame rate, as the BallSystem does,
+    // and returns where it is drawn on the last frame
+    fn simulate(start: glm::Vec3, frame_rate: f32, seconds: f32, input: &TickInput, normal: glm::Vec3) -> glm::Vec3 {
+        let params = BallParams::default();
+        let contact = plane(normal);
+        let mut ball = Ball::new(start);
+        let mut step = FixedStep::default();
+        let mut input = *input;
+
+        for _ in 0..(seconds * frame_rate).round() as u32 {
+            let steps = step.advance(1.0 / frame_rate);
+            ball.frame(&mut input, steps, &params, &contact, |_, _, _, _| ());
+        };
+        physics::interpolate(&ball.previous, &ball.current, step.alpha())
+    }
+
+    // Drops the ball onto the plane and rolls it for a few seconds at each frame rate
+    fn roll(normal: glm::Vec3, input: TickInput) -> Vec<glm::Vec3> {
+        ROLL_FRAME_RATES.iter()
+            .map(|&frame_rate| simulate(glm::vec3(0.0, 2.0, 0.0), frame_rate, 3.0, &input, normal))
+            .collect()
+    }
+
+    fn assert_same_ends(ends: &[glm::Vec3]) {
+        let drift = glm::distance(&ends[0], &ends[1]);
+        assert!(drift <= ROLL_TOLERANCE, "Ball ended {} world units apart at different frame rates: {:?}", drift, ends);
+    }
+
+    #[test]
+    fn rolls_the_same_at_any_frame_rate_on_flat_ground() {
+        let input = TickInput { steer: 0.5, lean: 0.5, push: [0.25, 0.0], ..TickInput::default() };
+        let ends = roll(glm::vec3(0.0, 1.0, 0.0), input);
+        assert_same_ends(&ends);
+        assert!(ends[0].x > 1.0, "Ball should have been pushed along: {:?}", ends[0]);
+    }
+
+    #[test]
+    fn rolls_the_same_at_any_frame_rate_down_a_slope() {
+        let ends = roll(glm::vec3(0.4, 1.0, 0.0), TickInput::default());
+        assert_same_ends(&ends);
+        assert!(ends[0].x > 1.0, "Ball should have rolled downhill: {:?}", ends[0]);
+    }
+
+    #[test]
+    fn brakes_the_same_at_any_frame_rate() {
+        let input = TickInput { brake: true, ..TickInput::default() };
+        let ends = roll(glm::vec3(0.4, 1.0, 0.0), input);
+        assert_same_ends(&ends);
+    }
+
+    #[test]
+    fn comes_to_rest_on_the_surface() {
+        let end = simulate(glm::vec3(0.0, 2.0, 0.0), 60.0, 3.0, &TickInput::default(), glm::vec3(0.0, 1.0, 0.0));
+        let radius = BallParams::default().radius;
+        assert!((end.y - radius).abs() <= 1e-4, "Ball should be resting on the ground, not above it: {:?}", end);
+    }
+
+    #[test]
+    fn heading_is_unit_length_on_flat_ground_and_slopes() {
+        for normal in [glm::vec3(0.0, 1.0, 0.0), glm::vec3(0.01, 1.0, 0.0), glm::vec3(0.4, 1.0, -0.3)].iter() {
+            let normal = normal.normalize();
+            for &rotation in [0.0, 1.0, 4.0].iter() {
+                let (dir, angle) = heading(&normal, rotation);
+                for v in [dir, angle].iter() {
+                    assert!((glm::length(v) - 1.0).abs() <= 1e-5, "Heading should be unit length: {:?} on {:?}", v, normal);
+                    assert!(glm::dot(v, &normal).abs() <= 1e-5, "Heading should be along the surface: {:?} on {:?}", v, normal);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn leans_on_flat_ground() {
+        let input = TickInput { lean: 1.0, ..TickInput::default() };
+        let end = simulate(glm::vec3(0.0, 0.5, 0.0), 60.0, 1.0, &input, glm::vec3(0.0, 1.0, 0.0));
+        assert!(end.z < -0.5, "Leaning should have moved the ball away from the camera: {:?}", end);
+    }
+
+    #[test]
+    fn jumps_once_per_press() {
+        let params = BallParams::default();
+        let ground = plane(glm::vec3(0.0, 1.0, 0.0));
+        let mut ball = Ball::new(glm::vec3(0.0, params.radius, 0.0));
+        let mut input = TickInput { jump: true, ..TickInput::default() };
+
+        // A frame without a physics step keeps the jump for the next one
+        ball.frame(&mut input, 0, &params, &ground, |_, _, _, _| ());
+        assert!(input.jump);
+
+        let mut jumps = Vec::new();
+        ball.frame(&mut input, 4, &params, &ground, |step, _, input, _| jumps.push((step, input.jump)));
+        assert_eq!(jumps, vec![(0, true), (1, false), (2, false), (3, false)]);
+        assert!(!input.jump);
+        assert!(ball.current.velocity.y > 0.5 * params.jump_speed && ball.current.velocity.y < params.jump_speed,
+                "Ball should have jumped once: {:?}", ball.current.velocity);
+    }
+}
@@ -0,0 +1,213 @@
+// Named player actions, and the pad buttons, axes, keys and mouse buttons bound to them.
+// The bindings are loaded from resources/controls.ron so players can remap them.
+
+use amethyst::renderer::{MouseButton, VirtualKeyCode};
+use gilrs::{Axis, Button};
+use ron;
+
+use std::fs;
+use std::io;
+
+/// Pad axes, named as in gilrs
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
+pub enum PadAxis {
+    LeftStickX,
+    LeftStickY,
+    LeftZ,
+    RightStickX,
+    RightStickY,
+    RightZ,
+    DPadX,
+    DPadY
+}
+
+impl PadAxis {
+    pub fn from_gilrs(axis: Axis) -> Option<Self> {
+        match axis {
+            Axis::LeftStickX => Some(PadAxis::LeftStickX),
+            Axis::LeftStickY => Some(PadAxis::LeftStickY),
+            Axis::LeftZ => Some(PadAxis::LeftZ),
+            Axis::RightStickX => Some(PadAxis::RightStickX),
+            Axis::RightStickY => Some(PadAxis::RightStickY),
+            Axis::RightZ => Some(PadAxis::RightZ),
+            Axis::DPadX => Some(PadAxis::DPadX),
+            Axis::DPadY => Some(PadAxis::DPadY),
+            _ => None
+        }
+    }
+}
+
+/// Pad buttons, named as in gilrs
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
+pub enum PadButton {
+    South,
+    East,
+    North,
+    West,
+    LeftTrigger,
+    LeftTrigger2,
+    RightTrigger,
+    RightTrigger2,
+    Select,
+    Start,
+    LeftThumb,
+    RightThumb,
+    DPadUp,
+    DPadDown,
+    DPadLeft,
+    DPadRight
+}
+
+impl PadButton {
+    pub fn from_gilrs(button: Button) -> Option<Self> {
+        match button {
+            Button::South => Some(PadButton::South),
+            Button::East => Some(PadButton::East),
+            Button::North => Some(PadButton::North),
+            Button::West => Some(PadButton::West),
+            Button::LeftTrigger => Some(PadButton::LeftTrigger),
+            Button::LeftTrigger2 => Some(PadButton::LeftTrigger2),
+            Button::RightTrigger => Some(PadButton::RightTrigger),
+            Button::RightTrigger2 => Some(PadButton::RightTrigger2),
+            Button::Select => Some(PadButton::Select),
+            Button::Start => Some(PadButton::Start),
+            Button::LeftThumb => Some(PadButton::LeftThumb),
+            Button::RightThumb => Some(PadButton::RightThumb),
+            Button::DPadUp => Some(PadButton::DPadUp),
+            Button::DPadDown => Some(PadButton::DPadDown),
+            Button::DPadLeft => Some(PadButton::DPadLeft),
+            Button::DPadRight => Some(PadButton::DPadRight),
+            _ => None
+        }
+    }
+}
+
+/// How a raw axis value is shaped before it reaches an action
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub enum Curve {
+    Linear,
+    /// Raises the value to a power, above 1 for finer control near the center
+    Power(f32),
+    /// Eases in and out of the ends
+    Smooth
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AxisSettings {
+    /// Values closer to the center than this read as 0, and the rest is rescaled to start from 0
+    pub dead_zone: f32,
+    pub invert: bool,
+    pub curve: Curve,
+    /// Multiplies the value after the curve
+    pub sensitivity: f32
+}
+
+impl Default for AxisSettings {
+    fn default() -> Self {
+        AxisSettings {
+            dead_zone: 0.1,
+            invert: false,
+            curve: Curve::Linear,
+            sensitivity: 1.0
+        }
+    }
+}
+
+impl AxisSettings {
+    /// Shapes a raw value from -1 to 1.
+    pub fn apply(&self, value: f32) -> f32 {
+        let magnitude = ((value.abs() - self.dead_zone) / (1.0 - self.dead_zone)).max(0.0).min(1.0);
+        let curved = match self.curve {
+            Curve::Linear => magnitude,
+            Curve::Power(exponent) => magnitude.powf(exponent),
+            Curve::Smooth => magnitude * magnitude * (3.0 - 2.0 * magnitude)
+        };
+        let value = (if value < 0.0 { -curved } else { curved }) * self.sensitivity;
+        if self.invert { -value } else { value }
+    }
+}
+
+/// Something that moves an axis action between -1 and 1
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum AxisBinding {
+    Pad(PadAxis, AxisSettings),
+    PadButtons { negative: PadButton, positive: PadButton },
+    Keys { negative: VirtualKeyCode, positive: VirtualKeyCode },
+    /// Moving the mouse sideways while holding a button, full lock after the given pixels in a frame
+    MouseDrag { button: MouseButton, pixels: f32, settings: AxisSettings }
+}
+
+/// Something that triggers a button action
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub enum ButtonBinding {
+    Pad(PadButton),
+    Key(VirtualKeyCode),
+    Mouse(MouseButton)
+}
+
+/// The bindings for every player action. Keyboard and mouse bindings only apply to player one.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ActionMap {
+    /// Turning, negative to the left
+    pub steer: Vec<AxisBinding>,
+    /// Leaning the weight forwards to speed up, or back to slow down
+    pub lean: Vec<AxisBinding>,
+    /// Pushing East, and pushing South (towards -z)
+    pub push_x: Vec<AxisBinding>,
+    pub push_y: Vec<AxisBinding>,
+    pub jump: Vec<ButtonBinding>,
+    pub brake: Vec<ButtonBinding>,
+    /// Putting the ball back where it started
+    pub reset: Vec<ButtonBinding>
+}
+
+impl Default for ActionMap {
+    fn default() -> Self {
+        use self::AxisBinding::*;
+        use self::ButtonBinding::{Key, Mouse};
+
+        let stick = AxisSettings::default();
+        ActionMap {
+            steer: vec![
+                Pad(PadAxis::LeftStickX, stick),
+                Keys { negative: VirtualKeyCode::Left, positive: VirtualKeyCode::Right },
+                MouseDrag { button: MouseButton::Right, pixels: 40.0, settings: AxisSettings { dead_zone: 0.0, ..stick } }
+            ],
+            lean: vec![
+                Pad(PadAxis::LeftStickY, stick),
+                Keys { negative: VirtualKeyCode::Down, positive: VirtualKeyCode::Up }
+            ],
+            push_x: vec![
+                Pad(PadAxis::RightStickX, stick),
+                Keys { negative: VirtualKeyCode::A, positive: VirtualKeyCode::D }
+            ],
+            push_y: vec![
+                Pad(PadAxis::RightStickY, stick),
+                Keys { negative: VirtualKeyCode::S, positive: VirtualKeyCode::W }
+            ],
+            jump: vec![ButtonBinding::Pad(PadButton::South), Key(VirtualKeyCode::Space), Mouse(MouseButton::Left)],
+            brake: vec![ButtonBinding::Pad(PadButton::West), Key(VirtualKeyCode::LShift)],
+            reset: vec![ButtonBinding::Pad(PadButton::North), Key(VirtualKeyCode::R)]
+        }
+    }
+}
+
+impl ActionMap {
+    pub fn from_ron(ron: &str) -> ron::de::Result<Self> {
+        ron::de::from_str(ron)
+    }
+
+    /// Load bindings from a RON file, falling back to the defaults if it can't be read.
+    pub fn load(path: &str) -> Self {
+        let result = fs::read_to_string(path)
+            .and_then(|ron| ActionMap::from_ron(&ron).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string())));
+        match result {
+            Ok(map) => map,
+            Err(err) => {
+                println!("Failed to load controls from {}, using the defaults: {}", path, err);
+                ActionMap::default()
+            }
+        }
+    }
+}
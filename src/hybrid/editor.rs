@@ -0,0 +1,181 @@
+use amethyst::{
+    prelude::*,
+    ecs::prelude::*,
+    core::Transform,
+    core::timing::Time,
+    renderer::DebugLinesComponent
+};
+use gilrs::{Event, Button, Axis::*};
+use gilrs::ev::EventType::*;
+use glm;
+
+use super::{draw_control_plane, Ball, DebugOverlay, CHUNK_SCALE};
+use super::controls::AxisSettings;
+use super::proc_geom::{ControlPlane, Dir8};
+use super::terrain::Terrain;
+
+// Units per second at full stick deflection, in control planes
+const MOVE_SPEED: f32 = 1.0;
+const ROTATE_SPEED: f32 = 1.0;
+const EXTEND_SPEED: f32 = 0.5;
+
+/// Editor state, pushed from Hybrid with Start. Selects a control plane with the d-pad and reshapes it:
+///
+/// * Left stick moves the plane, bumpers lower and raise it
+/// * Right stick tilts the plane, triggers turn it
+/// * Holding a face button and pushing the right stick up or down drags the handle on that side
+///   (North, East, South, West)
+/// * Select toggles the debug overlay, as in game
+pub struct Editor {
+    selected: (i32, i32),
+    // Dead zone and response applied to the sticks, so a drifting stick doesn't keep editing
+    stick: AxisSettings,
+    left_stick: glm::Vec2,
+    right_stick: glm::Vec2,
+    // Bumpers and triggers, each -1, 0 or 1 for left, none or right
+    raise: f32,
+    turn: f32,
+    handle: Option<Dir8>,
+    highlight: Option<Entity>
+}
+
+impl Editor {
+    pub fn new() -> Self {
+        Editor {
+            selected: (0, 0),
+            stick: AxisSettings::default(),
+            left_stick: glm::vec2(0.0, 0.0),
+            right_stick: glm::vec2(0.0, 0.0),
+            raise: 0.0,
+            turn: 0.0,
+            handle: None,
+            highlight: None
+        }
+    }
+
+    // Apply the held sticks and buttons to the selected plane
+    fn edit(&self, plane: &mut ControlPlane, dt: f32) {
+        plane.translate(&(glm::vec3(self.left_stick.x, self.raise, -self.left_stick.y) * MOVE_SPEED * dt));
+        plane.rotate(self.turn * ROTATE_SPEED * dt, &glm::vec3(0.0, 1.0, 0.0));
+
+        match self.handle {
+            Some(dir) => plane.extend(dir, self.right_stick.y * EXTEND_SPEED * dt),
+            None => {
+                plane.rotate(-self.right_stick.x * ROTATE_SPEED * dt, &glm::vec3(0.0, 0.0, 1.0));
+                plane.rotate(-self.right_stick.y * ROTATE_SPEED * dt, &glm::vec3(1.0, 0.0, 0.0));
+            }
+        }
+    }
+
+    // The sticks are already zero within their dead zone
+    fn is_editing(&self) -> bool {
+        self.left_stick.magnitude() > 0.0 || self.right_stick.magnitude() > 0.0 || self.raise != 0.0 || self.turn != 0.0
+    }
+
+    // Outline the selected plane and its handles
+    fn draw(&self, world: &World) {
+        let plane = match world.read_resource::<Terrain>().source.plane(self.selected.0, self.selected.1) {
+            Some(plane) => plane,
+            None => return
+        };
+        let entity = match self.highlight {
+            Some(entity) => entity,
+            None => return
+        };
+        let mut storage = world.write_storage::<DebugLinesComponent>();
+        let lines = match storage.get_mut(entity) {
+            Some(lines) => lines,
+            None => return
+        };
+        lines.clear();
+        draw_control_plane(lines, &plane, self.handle);
+    }
+}
+
+impl<'a, 'b> State<GameData<'a, 'b>, Event> for Editor {
+    fn on_start(&mut self, data: StateData<GameData>) {
+        let world = data.world;
+
+        // Start with the plane under the ball
+        let ball_position = {
+            let balls = world.read_storage::<Ball>();
+            let transforms = world.read_storage::<Transform>();
+            (&balls, &transforms).join().next().map(|(_ball, transform)| *transform.translation())
+        };
+        if let Some(p) = ball_position {
+            self.selected = ((p.x / CHUNK_SCALE).round() as i32, (p.z / CHUNK_SCALE).round() as i32);
+        };
+
+        self.highlight = Some(world.create_entity().with(DebugLinesComponent::new()).build());
+        println!("Editor: selected control plane {:?}", self.selected);
+    }
+
+    fn on_stop(&mut self, data: StateData<GameData>) {
+        if let Some(entity) = self.highlight.take() {
+            data.world.delete_entity(entity).expect("Highlight entity is alive");
+        }
+    }
+
+    fn handle_event(
+        &mut self,
+        data: StateData<GameData>,
+        event: Event
+    ) -> Trans<GameData<'a, 'b>, Event> {
+        let selected = self.selected;
+        match event.event {
+            ButtonPressed(Button::Start, _) => return Trans::Pop,
+            ButtonPressed(Button::Select, _) => {
+                let mut overlay = data.world.write_resource::<DebugOverlay>();
+                overlay.enabled = !overlay.enabled;
+            },
+
+            ButtonPressed(Button::DPadUp, _) => self.selected.1 -= 1,
+            ButtonPressed(Button::DPadDown, _) => self.selected.1 += 1,
+            ButtonPressed(Button::DPadLeft, _) => self.selected.0 -= 1,
+            ButtonPressed(Button::DPadRight, _) => self.selected.0 += 1,
+
+            ButtonPressed(Button::North, _) => self.handle = Some(Dir8::North),
+            ButtonPressed(Button::East, _) => self.handle = Some(Dir8::East),
+            ButtonPressed(Button::South, _) => self.handle = Some(Dir8::South),
+            ButtonPressed(Button::West, _) => self.handle = Some(Dir8::West),
+            ButtonReleased(Button::North, _) | ButtonReleased(Button::East, _) |
+            ButtonReleased(Button::South, _) | ButtonReleased(Button::West, _) => self.handle = None,
+
+            ButtonPressed(Button::LeftTrigger, _) => self.raise = -1.0,
+            ButtonPressed(Button::RightTrigger, _) => self.raise = 1.0,
+            ButtonReleased(Button::LeftTrigger, _) | ButtonReleased(Button::RightTrigger, _) => self.raise = 0.0,
+            ButtonPressed(Button::LeftTrigger2, _) => self.turn = -1.0,
+            ButtonPressed(Button::RightTrigger2, _) => self.turn = 1.0,
+            ButtonReleased(Button::LeftTrigger2, _) | ButtonReleased(Button::RightTrigger2, _) => self.turn = 0.0,
+
+            AxisChanged(LeftStickX, x, _) => self.left_stick.x = self.stick.apply(x),
+            AxisChanged(LeftStickY, y, _) => self.left_stick.y = self.stick.apply(y),
+            AxisChanged(RightStickX, x, _) => self.right_stick.x = self.stick.apply(x),
+            AxisChanged(RightStickY, y, _) => self.right_stick.y = self.stick.apply(y),
+
+            _ => ()
+        };
+
+        if self.selected != selected {
+            println!("Editor: selected control plane {:?}", self.selected);
+        };
+        Trans::None
+    }
+
+    fn update(&mut self, data: StateData<GameData>) -> Trans<GameData<'a, 'b>, Event> {
+        if self.is_editing() {
+            let dt = data.world.read_resource::<Time>().delta_seconds();
+            let mut terrain = data.world.write_resource::<Terrain>();
+            let (i, j) = self.selected;
+
+            if let Some(mut plane) = terrain.source.plane(i, j) {
+                self.edit(&mut plane, dt);
+                terrain.edit(i, j, plane);
+            }
+        };
+        self.draw(&data.world);
+
+        data.data.update(&data.world);
+        Trans::None
+    }
+}
@@ -0,0 +1,181 @@
+// Export the tessellated terrain for use in other tools, e.g. Blender.
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use glm;
+
+use super::CHUNK_SCALE;
+use super::proc_geom::{ControlSurface, IndexedMesh};
+use super::terrain::{ALBEDO_XY, ALBEDO_YZ, ALBEDO_XZ};
+
+/// A vertex of the exported surface, in world units.
+pub struct ExportVertex {
+    pub position: glm::Vec3,
+    pub normal: glm::Vec3,
+    /// Texture coordinates across the whole surface, rather than per patch
+    pub tex_coord: glm::Vec2
+}
+
+/// Tessellate every patch of the surface at res * res, into a single mesh.
+pub fn tessellate(cs: &ControlSurface, res: i32) -> IndexedMesh<ExportVertex> {
+    let mut vertices = Vec::new();
+    let mut indices = Vec::new();
+
+    let (patches_x, patches_z) = (cs.width() - 1, cs.height() - 1);
+    for i in 0..patches_x {
+        for j in 0..patches_z {
+            let mesh = cs.patch(i, j).rasterize_indexed_with(res, [res; 4], |p, n, u, v| ExportVertex {
+                position: p * CHUNK_SCALE,
+                normal: n,
+                tex_coord: glm::vec2((i as f32 + u) / patches_x as f32, (j as f32 + v) / patches_z as f32)
+            });
+
+            let offset = vertices.len();
+            vertices.extend(mesh.vertices);
+            indices.extend(mesh.indices.iter().map(|[a, b, c]| [a + offset, b + offset, c + offset]));
+        }
+    };
+
+    IndexedMesh { vertices, indices }
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default()
+}
+
+/// Write the surface to an OBJ file, along with a MTL material library next to it.
+pub fn write_obj<P: AsRef<Path>>(cs: &ControlSurface, res: i32, path: P) -> io::Result<()> {
+    let path = path.as_ref();
+    let mtl_path = path.with_extension("mtl");
+    let mesh = tessellate(cs, res);
+
+    let mut obj = BufWriter::new(File::create(path)?);
+    writeln!(obj, "# Hybrid terrain, {} * {} control planes", cs.width(), cs.height())?;
+    writeln!(obj, "mtllib {}", file_name(&mtl_path))?;
+    writeln!(obj, "o terrain")?;
+    for vertex in mesh.vertices.iter() {
+        writeln!(obj, "v {} {} {}", vertex.position.x, vertex.position.y, vertex.position.z)?;
+    };
+    for vertex in mesh.vertices.iter() {
+        writeln!(obj, "vn {} {} {}", vertex.normal.x, vertex.normal.y, vertex.normal.z)?;
+    };
+    for vertex in mesh.vertices.iter() {
+        writeln!(obj, "vt {} {}", vertex.tex_coord.x, vertex.tex_coord.y)?;
+    };
+    writeln!(obj, "usemtl terrain")?;
+    for [a, b, c] in mesh.indices.iter() {
+        // OBJ indices start at 1
+        let (a, b, c) = (a + 1, b + 1, c + 1);
+        writeln!(obj, "f {}/{}/{} {}/{}/{} {}/{}/{}", a, a, a, b, b, b, c, c, c)?;
+    };
+
+    // OBJ has no notion of triplanar mapping, so the textures per axis are left as comments
+    let mut mtl = BufWriter::new(File::create(&mtl_path)?);
+    writeln!(mtl, "# Triplanar material, textures are relative to the assets directory")?;
+    writeln!(mtl, "# xy: {}", ALBEDO_XY)?;
+    writeln!(mtl, "# yz: {}", ALBEDO_YZ)?;
+    writeln!(mtl, "# xz: {}", ALBEDO_XZ)?;
+    writeln!(mtl, "newmtl terrain")?;
+    writeln!(mtl, "Kd 1.0 1.0 1.0")?;
+    writeln!(mtl, "map_Kd {}", ALBEDO_XZ)?;
+
+    Ok(())
+}
+
+// glTF buffers are little endian
+fn push_f32(buffer: &mut Vec<u8>, value: f32) {
+    push_u32(buffer, value.to_bits())
+}
+
+fn push_u32(buffer: &mut Vec<u8>, value: u32) {
+    buffer.extend_from_slice(&[value as u8, (value >> 8) as u8, (value >> 16) as u8, (value >> 24) as u8])
+}
+
+/// Write the surface to a glTF 2.0 file, with its binary buffer in a .bin file next to it.
+/// The triplanar textures are recorded in the material's extras.
+pub fn write_gltf<P: AsRef<Path>>(cs: &ControlSurface, res: i32, path: P) -> io::Result<()> {
+    let path = path.as_ref();
+    let bin_path = path.with_extension("bin");
+    let mesh = tessellate(cs, res);
+    let count = mesh.vertices.len();
+
+    // Positions, normals, texture coordinates and indices, one after the other
+    let mut buffer = Vec::new();
+    let mut min = glm::vec3(::std::f32::MAX, ::std::f32::MAX, ::std::f32::MAX);
+    let mut max = glm::vec3(::std::f32::MIN, ::std::f32::MIN, ::std::f32::MIN);
+    for vertex in mesh.vertices.iter() {
+        for k in 0..3 {
+            min[k] = min[k].min(vertex.position[k]);
+            max[k] = max[k].max(vertex.position[k]);
+            push_f32(&mut buffer, vertex.position[k]);
+        }
+    };
+    for vertex in mesh.vertices.iter() {
+        for k in 0..3 {
+            push_f32(&mut buffer, vertex.normal[k]);
+        }
+    };
+    for vertex in mesh.vertices.iter() {
+        push_f32(&mut buffer, vertex.tex_coord.x);
+        // glTF puts the origin of texture space at the top left
+        push_f32(&mut buffer, 1.0 - vertex.tex_coord.y);
+    };
+    for triangle in mesh.indices.iter() {
+        for index in triangle.iter() {
+            push_u32(&mut buffer, *index as u32);
+        }
+    };
+
+    let (positions, normals, tex_coords) = (0, count * 12, count * 24);
+    let indices = count * 32;
+
+    let json = format!(r#"{{
+  "asset": {{ "version": "2.0", "generator": "hybrid" }},
+  "scene": 0,
+  "scenes": [{{ "nodes": [0] }}],
+  "nodes": [{{ "mesh": 0, "name": "terrain" }}],
+  "meshes": [{{
+    "name": "terrain",
+    "primitives": [{{
+      "attributes": {{ "POSITION": 0, "NORMAL": 1, "TEXCOORD_0": 2 }},
+      "indices": 3,
+      "material": 0
+    }}]
+  }}],
+  "materials": [{{
+    "name": "terrain",
+    "pbrMetallicRoughness": {{ "metallicFactor": 0.0, "roughnessFactor": 1.0 }},
+    "extras": {{ "triplanar": {{ "xy": "{xy}", "yz": "{yz}", "xz": "{xz}" }} }}
+  }}],
+  "buffers": [{{ "uri": "{bin}", "byteLength": {length} }}],
+  "bufferViews": [
+    {{ "buffer": 0, "byteOffset": {positions}, "byteLength": {vec3_length}, "target": 34962 }},
+    {{ "buffer": 0, "byteOffset": {normals}, "byteLength": {vec3_length}, "target": 34962 }},
+    {{ "buffer": 0, "byteOffset": {tex_coords}, "byteLength": {vec2_length}, "target": 34962 }},
+    {{ "buffer": 0, "byteOffset": {indices}, "byteLength": {index_length}, "target": 34963 }}
+  ],
+  "accessors": [
+    {{ "bufferView": 0, "componentType": 5126, "count": {count}, "type": "VEC3",
+       "min": [{min_x}, {min_y}, {min_z}], "max": [{max_x}, {max_y}, {max_z}] }},
+    {{ "bufferView": 1, "componentType": 5126, "count": {count}, "type": "VEC3" }},
+    {{ "bufferView": 2, "componentType": 5126, "count": {count}, "type": "VEC2" }},
+    {{ "bufferView": 3, "componentType": 5125, "count": {index_count}, "type": "SCALAR" }}
+  ]
+}}
+"#,
+        xy = ALBEDO_XY, yz = ALBEDO_YZ, xz = ALBEDO_XZ,
+        bin = file_name(&bin_path),
+        length = buffer.len(),
+        positions = positions, normals = normals, tex_coords = tex_coords, indices = indices,
+        vec3_length = count * 12, vec2_length = count * 8, index_length = mesh.indices.len() * 12,
+        count = count, index_count = mesh.indices.len() * 3,
+        min_x = min.x, min_y = min.y, min_z = min.z,
+        max_x = max.x, max_y = max.y, max_z = max.z
+    );
+
+    File::create(&bin_path)?.write_all(&buffer)?;
+    File::create(path)?.write_all(json.as_bytes())?;
+
+    Ok(())
+}
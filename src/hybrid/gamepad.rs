@@ -0,0 +1,210 @@
+// Gamepad support, owning gilrs and keeping the state of each connected pad.
+
+use gilrs::{Event, Gilrs};
+use gilrs::ev::EventType::*;
+use gilrs::ff::{BaseEffect, BaseEffectType, Effect, EffectBuilder, Replay, Ticks};
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use super::controls::{PadAxis, PadButton};
+use super::rumble::RumbleEffect;
+
+/// The last reported state of a pad, gilrs only tells us when something changes
+#[derive(Default)]
+pub struct PadState {
+    pub connected: bool,
+    pub name: String,
+    /// Whether the pad can rumble
+    pub force_feedback: bool,
+    pub axes: HashMap<PadAxis, f32>,
+    pub held: HashSet<PadButton>,
+    /// Pressed since the last frame, so a tap released within the frame still counts
+    pub pressed: HashSet<PadButton>,
+    /// The rumble playing on the pad, if any
+    pub rumble: Option<RumbleEffect>
+}
+
+impl PadState {
+    pub fn axis(&self, axis: PadAxis) -> f32 {
+        self.axes.get(&axis).cloned().unwrap_or(0.0)
+    }
+
+    pub fn button(&self, button: PadButton) -> bool {
+        self.held.contains(&button) || self.pressed.contains(&button)
+    }
+}
+
+/// Resource owning gilrs, polled once a frame by the game's event reader.
+/// Systems that only read pad state take `Read<Gamepads>`, systems that drive the pads
+/// (such as rumble) take `Write<Gamepads>`.
+pub struct Gamepads {
+    // None when gilrs couldn't start, and the game is played with the keyboard and mouse.
+    // The mutex only makes gilrs Sync, it is never locked as access goes through &mut self.
+    gilrs: Option<Mutex<Backend>>,
+    pads: HashMap<usize, PadState>
+}
+
+struct Backend {
+    gilrs: Gilrs,
+    // Effects stop when dropped, so they're kept until they finish
+    effects: HashMap<usize, (Effect, Instant)>
+}
+
+fn magnitude(strength: f32) -> u16 {
+    (strength.max(0.0).min(1.0) * f32::from(u16::max_value())) as u16
+}
+
+impl Default for Gamepads {
+    fn default() -> Self {
+        match Gilrs::new() {
+            Ok(gilrs) => Gamepads::new(gilrs),
+            Err(err) => {
+                println!("Gamepads unavailable, using keyboard and mouse: {}", err);
+                Gamepads { gilrs: None, pads: HashMap::new() }
+            }
+        }
+    }
+}
+
+impl Gamepads {
+    pub fn new(gilrs: Gilrs) -> Self {
+        let mut pads = HashMap::new();
+        for (id, gamepad) in gilrs.gamepads() {
+            println!("Gamepad {} connected: {}", id, gamepad.name());
+            pads.insert(id, PadState {
+                connected: true,
+                name: gamepad.name().to_string(),
+                force_feedback: gamepad.is_ff_supported(),
+                ..PadState::default()
+            });
+        };
+        Gamepads { gilrs: Some(Mutex::new(Backend { gilrs, effects: HashMap::new() })), pads }
+    }
+
+    fn backend(&mut self) -> Option<&mut Backend> {
+        // A poisoned mutex only means a panic elsewhere, gilrs itself is still fine
+        self.gilrs.as_mut().map(|backend| match backend.get_mut() {
+            Ok(backend) => backend,
+            Err(poisoned) => poisoned.into_inner()
+        })
+    }
+
+    /// gilrs itself. None if gamepads are unavailable.
+    pub fn gilrs(&mut self) -> Option<&mut Gilrs> {
+        self.backend().map(|backend| &mut backend.gilrs)
+    }
+
+    /// Plays a rumble on a pad, replacing any rumble already playing on it.
+    /// Pads without force feedback are left alone.
+    pub fn rumble(&mut self, id: usize, rumble: RumbleEffect) {
+        match self.pads.get(&id) {
+            Some(pad) if pad.connected && pad.force_feedback => (),
+            _ => return
+        };
+
+        let result = match self.backend() {
+            Some(backend) => {
+                let play_for = Ticks::from_ms(rumble.duration_ms);
+                let motor = |kind| BaseEffect {
+                    kind,
+                    scheduling: Replay { play_for, ..Default::default() },
+                    envelope: Default::default()
+                };
+                EffectBuilder::new()
+                    .add_effect(motor(BaseEffectType::Strong { magnitude: magnitude(rumble.strong) }))
+                    .add_effect(motor(BaseEffectType::Weak { magnitude: magnitude(rumble.weak) }))
+                    .gamepads(&[id])
+                    .finish(&mut backend.gilrs)
+                    .and_then(|effect| effect.play().map(|()| effect))
+                    .map(|effect| {
+                        let end = Instant::now() + Duration::from_millis(u64::from(rumble.duration_ms));
+                        backend.effects.insert(id, (effect, end));
+                    })
+            },
+            None => return
+        };
+
+        match result {
+            Ok(()) => if let Some(pad) = self.pads.get_mut(&id) {
+                pad.rumble = Some(rumble)
+            },
+            Err(err) => println!("Failed to rumble gamepad {}: {}", id, err)
+        }
+    }
+
+    pub fn pad(&self, id: usize) -> Option<&PadState> {
+        self.pads.get(&id)
+    }
+
+    pub fn pads<'a>(&'a self) -> impl Iterator<Item = (usize, &'a PadState)> + 'a {
+        self.pads.iter().map(|(id, pad)| (*id, pad))
+    }
+
+    /// Takes the events gilrs has queued since the last frame, updating each pad's state.
+    pub fn poll(&mut self, events: &mut Vec<Event>) {
+        for pad in self.pads.values_mut() {
+            pad.pressed.clear();
+        };
+
+        let backend = match self.gilrs.as_mut() {
+            Some(backend) => match backend.get_mut() {
+                Ok(backend) => backend,
+                Err(poisoned) => poisoned.into_inner()
+            },
+            None => return
+        };
+
+        // Let go of rumbles that have finished
+        let now = Instant::now();
+        let finished: Vec<usize> = backend.effects.iter()
+            .filter(|(_, (_, end))| *end <= now)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in finished {
+            backend.effects.remove(&id);
+            if let Some(pad) = self.pads.get_mut(&id) {
+                pad.rumble = None;
+            }
+        };
+
+        let gilrs = &mut backend.gilrs;
+
+        while let Some(event) = gilrs.next_event() {
+            let pad = self.pads.entry(event.id).or_insert_with(PadState::default);
+            match event.event {
+                Connected => {
+                    let gamepad = gilrs.gamepad(event.id);
+                    println!("Gamepad {} connected: {}", event.id, gamepad.name());
+                    *pad = PadState {
+                        connected: true,
+                        name: gamepad.name().to_string(),
+                        force_feedback: gamepad.is_ff_supported(),
+                        ..PadState::default()
+                    };
+                },
+                Disconnected => {
+                    println!("Gamepad {} disconnected: {}", event.id, pad.name);
+                    backend.effects.remove(&event.id);
+                    *pad = PadState {
+                        name: pad.name.clone(),
+                        ..PadState::default()
+                    };
+                },
+                ButtonPressed(button, _) => if let Some(button) = PadButton::from_gilrs(button) {
+                    pad.held.insert(button);
+                    pad.pressed.insert(button);
+                },
+                ButtonReleased(button, _) => if let Some(button) = PadButton::from_gilrs(button) {
+                    pad.held.remove(&button);
+                },
+                AxisChanged(axis, value, _) => if let Some(axis) = PadAxis::from_gilrs(axis) {
+                    pad.axes.insert(axis, value);
+                },
+                _ => ()
+            };
+            events.push(event);
+        }
+    }
+}
@@ -2,52 +2,191 @@ use amethyst::{
     prelude::*,
     ecs::prelude::*,
     core::Transform,
-    core::nalgebra::{Vector3, Isometry3},
+    core::nalgebra::{Point3, Vector3},
     assets::{Loader, AssetStorage},
-    renderer::{MeshHandle, DebugLinesComponent, JpgFormat, Texture, TextureHandle, TriplanarMaterial, Rgba, Projection, SkyboxColor,
-               PosNormTex, Camera, AmbientColor, Material, MaterialDefaults, TextureMetadata, ObjFormat, Light, DirectionalLight, PointLight},
+    renderer::{DebugLinesComponent, JpgFormat, Texture, TextureHandle, Rgba, Projection, SkyboxColor, ActiveCamera,
+               Camera, AmbientColor, Material, MaterialDefaults, MeshHandle, TextureMetadata, ObjFormat, Light, DirectionalLight, PointLight},
 };
-use gilrs::Event;
+use gilrs::{Button, Event};
+use gilrs::ev::EventType::ButtonPressed;
+use glm;
 use ncollide3d::{
     shape::TriMesh,
-    bounding_volume::{AABB, HasBoundingVolume, BoundingVolume}
+    bounding_volume::AABB,
+    query::Ray
 };
 
 use rand::{thread_rng, Rng};
 use std::f32::consts::*;
-
+use std::path::Path;
+
+pub mod ball;
+pub mod controls;
+mod editor;
+pub mod export;
+pub mod gamepad;
+mod noise;
+pub mod physics;
 mod proc_geom;
+pub mod replay;
+pub mod rumble;
+mod spatial;
+pub mod terrain;
+
+pub use self::ball::{Ball, TickInput};
+pub use self::proc_geom::{BicubicPatch, ControlSurface, Dir8, Downhill, Profile};
+use self::proc_geom::ControlPlane;
+pub use self::spatial::ChunkIndex;
+pub use self::terrain::TerrainConfig;
+use self::terrain::{Terrain, TerrainMaterials, TerrainSource};
+
+/// World units spanned by a single bezier patch
+pub const CHUNK_SCALE: f32 = 8.0;
+
+/// Players sharing the screen, each with their own pad
+pub const MAX_PLAYERS: usize = 4;
 
+/// A camera keeping every ball in `entities` in view.
 pub struct Follow {
-    pub entity: Entity
+    pub entities: Vec<Entity>
 }
 
 impl Component for Follow {
     type Storage = DenseVecStorage<Self>;
 }
 
-pub struct Ball {
-    pub velocity: [f32; 2]
+/// What the player steering a ball wants it to do, from their pad and, for player one, the keyboard and mouse.
+/// Written by the PlayerInputSystem from the bindings in resources/controls.ron.
+pub struct PlayerInput {
+    /// Numbered from 0 in the order players joined
+    pub player: usize,
+    /// The gilrs id of the player's pad, None until a pad is assigned
+    pub pad: Option<usize>,
+    /// Turning, from -1 for full left to 1 for full right
+    pub steer: f32,
+    /// Leaning forwards to speed up along the heading, or back to slow down, from -1 to 1
+    pub lean: f32,
+    /// Pushing across the ground, x East and y South (towards -z, away from the camera), up to length 1
+    pub push: glm::Vec2,
+    pub brake: bool,
+    /// Set when jump is pressed, and cleared once the ball has jumped
+    pub jump: bool,
+    /// Set when reset is pressed, and cleared once the ball is back at its spawn point
+    pub reset: bool
+}
+
+impl PlayerInput {
+    pub fn new(player: usize, pad: Option<usize>) -> Self {
+        PlayerInput {
+            player,
+            pad,
+            steer: 0.0,
+            lean: 0.0,
+            push: glm::vec2(0.0, 0.0),
+            brake: false,
+            jump: false,
+            reset: false
+        }
+    }
+
+    /// The controls for the next physics step.
+    pub fn tick_input(&self) -> TickInput {
+        TickInput {
+            steer: self.steer,
+            lean: self.lean,
+            push: [self.push.x, self.push.y],
+            brake: self.brake,
+            jump: self.jump,
+            reset: self.reset
+        }
+    }
 }
 
-impl Component for Ball {
+impl Component for PlayerInput {
     type Storage = DenseVecStorage<Self>;
 }
 
 // The bezier patches that make up the terrain are marked with the Chunk component
 pub struct Chunk {
+    /// Position of the patch in the grid of control planes
+    pub coord: (i32, i32),
     pub collision_mesh: TriMesh<f32>,
-    pub patch: proc_geom::BicubicPatch,
+    pub patch: BicubicPatch,
     pub bounding_box: AABB<f32>
 }
 
 impl Component for Chunk {
+    type Storage = FlaggedStorage<Self, VecStorage<Self>>;
+}
+
+impl Chunk {
+    /// Whether the chunk's bounding box holds the point.
+    pub fn bounds(&self, point: &glm::Vec3) -> bool {
+        let (mins, maxs) = (self.bounding_box.mins(), self.bounding_box.maxs());
+        (0..3).all(|k| mins[k] <= point[k] && point[k] <= maxs[k])
+    }
+
+    /// The surface under a point, or above it if the point has sunk through.
+    pub fn contact(&self, position: &glm::Vec3) -> Option<physics::Contact> {
+        // We find our intersection point with the bezier surface by first raycasting down, and if that fails raycast up.
+        for direction in [-1.0, 1.0].iter() {
+            let ray = Ray {
+                origin: Point3::from(*position),
+                dir: glm::vec3(0.0, *direction, 0.0)
+            };
+
+            if let Some(hit) = self.patch.ray_intersect(&self.collision_mesh, CHUNK_SCALE, &ray) {
+                return Some(physics::Contact { point: hit.point, normal: hit.normal })
+            }
+        };
+        None
+    }
+}
+
+/// The surface under a position, among chunks that may hold it. Chunks are tried in order of their
+/// coordinates, so the game and headless replays agree on the contact wherever chunks overlap.
+pub fn surface_contact<'a, I>(chunks: I, position: &glm::Vec3) -> Option<physics::Contact>
+where
+    I: IntoIterator<Item = &'a Chunk>
+{
+    let mut chunks: Vec<&Chunk> = chunks.into_iter().filter(|chunk| chunk.bounds(position)).collect();
+    chunks.sort_by_key(|chunk| chunk.coord);
+    chunks.iter().filter_map(|chunk| chunk.contact(position)).next()
+}
+
+// The tessellation a chunk's mesh was last generated with, see LodSystem.
+pub struct Lod {
+    pub res: i32,
+    pub edges: [i32; 4]
+}
+
+impl Component for Lod {
     type Storage = VecStorage<Self>;
 }
 
-fn create_mesh(world: &World, vertices: Vec<PosNormTex>) -> MeshHandle {
-    let loader = world.read_resource::<Loader>();
-    loader.load_from_data(vertices.into(), (), &world.read_resource())
+/// Whether to draw control planes, patch hulls, chunk bounds and ball vectors. Toggled with Select.
+#[derive(Default)]
+pub struct DebugOverlay {
+    pub enabled: bool
+}
+
+/// A point on the control surface, in world units.
+pub fn world_point(p: glm::Vec3) -> Point3<f32> {
+    Point3::from(p * CHUNK_SCALE)
+}
+
+/// Outline a control plane in white, with lines from its center to each of its handle points.
+/// The lines are green, apart from the highlighted handle's which is red.
+pub fn draw_control_plane(lines: &mut DebugLinesComponent, plane: &ControlPlane, highlight: Option<Dir8>) {
+    let corners = [Dir8::NorthEast, Dir8::SouthEast, Dir8::SouthWest, Dir8::NorthWest];
+    for k in 0..4 {
+        lines.add_line(world_point(plane.point(corners[k])), world_point(plane.point(corners[(k + 1) % 4])), Rgba::white());
+    };
+    let center = world_point(plane.center());
+    for dir in Dir8::ALL.iter() {
+        let color = if highlight == Some(*dir) { Rgba::red() } else { Rgba::green() };
+        lines.add_line(center, world_point(plane.point(*dir)), color);
+    };
 }
 
 pub fn load_texture<N>(name: N, world: &World) -> TextureHandle
@@ -64,14 +203,31 @@ where
     )
 }
 
-pub struct Hybrid;
+// Mesh and material shared by every player's ball
+struct BallAssets {
+    mesh: MeshHandle,
+    mtl: Material
+}
+
+pub struct Hybrid {
+    pub terrain: TerrainConfig,
+    /// Where to save the course in the resources directory when the game exits
+    pub save_course: Option<String>,
+    /// Where to export the course mesh as .obj and .gltf files when the game exits
+    pub export: Option<String>,
+    /// Where to save a recording of every player's input when the game exits
+    pub record: Option<String>
+}
 
 impl<'a, 'b> State<GameData<'a, 'b>, Event> for Hybrid {
     fn on_start(&mut self, data: StateData<GameData>) {
         let world = data.world;
 
         world.register::<Chunk>();
+        world.register::<Lod>();
         world.register::<Follow>();
+        world.register::<Ball>();
+        world.register::<PlayerInput>();
 
         initialize_lights(world);
 
@@ -93,36 +249,23 @@ impl<'a, 'b> State<GameData<'a, 'b>, Event> for Hybrid {
             (mesh, mat)
         };
 
-        let mut trans = Transform::default();
-        trans.set_scale(0.3, 0.3, 0.3);
-        trans.set_position(Vector3::new(5.0, 30.0, 5.0));
+        world.add_resource(BallAssets { mesh, mtl });
 
-        world.add_resource(
-            Vec::<Event>::new(),
-        );
-
-        let player = world
-            .create_entity()
-            .with(mesh)
-            .with(mtl.clone())
-            .with(trans)
-            .with(DebugLinesComponent::new())
-            .with(Ball {
-                velocity: [0.0, 0.0]
-            })
-            .build();
-
-        initialize_camera(world, player);
+        // Player one takes the first pad to send an event, the others join by pressing a button
+        let player = spawn_player(world, 0, None);
+        let camera = initialize_camera(world, player);
+        world.add_resource(ActiveCamera { entity: camera });
 
         // Control Surface
-        let cs = proc_geom::ControlSurface::new();
+        let source = self.terrain.source(world);
+        world.add_resource(Terrain::new(source, 24));
 
         let mtl_xy = {
             let loader = world.read_resource::<Loader>();
             let mat_defaults = world.read_resource::<MaterialDefaults>();
 
             let mut rng = thread_rng();
-            let albedo = load_texture("texture/Rock08_col.jpg", world);
+            let albedo = load_texture(terrain::ALBEDO_XY, world);
 
             Material {
                 albedo,
@@ -134,7 +277,7 @@ impl<'a, 'b> State<GameData<'a, 'b>, Event> for Hybrid {
             let mat_defaults = world.read_resource::<MaterialDefaults>();
 
             let mut rng = thread_rng();
-            let albedo = load_texture("texture/Rock07_col.jpg", world);
+            let albedo = load_texture(terrain::ALBEDO_YZ, world);
             let emission = load_texture("texture/noise.jpg", world);
 
             Material {
@@ -148,7 +291,7 @@ impl<'a, 'b> State<GameData<'a, 'b>, Event> for Hybrid {
             let mat_defaults = world.read_resource::<MaterialDefaults>();
 
             let mut rng = thread_rng();
-            let albedo = load_texture("texture/Ice04_col.jpg", world);
+            let albedo = load_texture(terrain::ALBEDO_XZ, world);
             let emission = load_texture("texture/Snow01_col.jpg", world);
 
             Material {
@@ -157,41 +300,56 @@ impl<'a, 'b> State<GameData<'a, 'b>, Event> for Hybrid {
                 ..mat_defaults.0.clone()
             }
         };
-        // Create grid of bicubic patches
-        for i in 0..63 {
-            for j in 0..63 {
-                println!("i: {} j: {}", i, j);
-
-                let patch = proc_geom::BicubicPatch::new(
-                    &cs.controls[i][j],
-                    &cs.controls[i][j + 1],
-                    &cs.controls[i + 1][j + 1],
-                    &cs.controls[i + 1][j]
-                );
-                let mesh = create_mesh(world, patch.rasterize(2));
-                let mut collision_mesh = patch.collision_mesh(8, 8.0);
-
-                let mut trans = Transform::default();
-                trans.set_scale(8.0, 8.0, 8.0);
-                trans.set_x(0.0);
-                let mut bounding_box: AABB<f32> = collision_mesh.clone().bounding_volume(&Isometry3::identity());
-                bounding_box.loosen(3.0);
-
-                world
-                    .create_entity()
-                    .with(mesh)
-                    .with(TriplanarMaterial {
-                        mtl_xy: mtl_xy.clone(),
-                        mtl_yz: mtl_yz.clone(),
-                        mtl_xz: mtl_xz.clone()
-                    })
-                    .with(trans)
-                    .with(Chunk {
-                        collision_mesh: collision_mesh,
-                        patch: patch,
-                        bounding_box: bounding_box
-                    })
-                    .build();
+        // The chunks themselves are spawned around the ball by the TerrainStreamSystem
+        world.add_resource(TerrainMaterials { mtl_xy, mtl_yz, mtl_xz });
+
+        if self.record.is_some() {
+            match self.terrain {
+                TerrainConfig::Seeded { seed, size, profile } => {
+                    let terrain = replay::RecordedTerrain { seed, size, profile };
+                    world.add_resource(replay::Recorder { recording: Some(replay::Recording::new(terrain)), tick: 0 });
+                },
+                _ => println!("Only seeded terrain can be recorded, the game won't be recorded")
+            }
+        };
+    }
+
+    fn on_stop(&mut self, data: StateData<GameData>) {
+        if let Some(ref path) = self.record {
+            if let Some(ref recording) = data.world.read_resource::<replay::Recorder>().recording {
+                match recording.save(path) {
+                    Ok(()) => println!("Saved recording to {}", path),
+                    Err(err) => println!("Failed to save recording to {}: {}", path, err)
+                }
+            }
+        };
+
+        let terrain = data.world.read_resource::<Terrain>();
+        let cs = match terrain.source {
+            TerrainSource::Surface(ref cs) => cs,
+            _ => {
+                if self.save_course.is_some() || self.export.is_some() {
+                    println!("Only bounded courses can be saved or exported, give the terrain a width and height")
+                };
+                return
+            }
+        };
+
+        if let Some(ref path) = self.save_course {
+            match terrain::save_course(cs, path) {
+                Ok(()) => println!("Saved course to resources/{}", path),
+                Err(err) => println!("Failed to save course to resources/{}: {}", path, err)
+            }
+        };
+
+        if let Some(ref path) = self.export {
+            const EXPORT_RES: i32 = 8;
+            let path = Path::new(path);
+            let result = export::write_obj(cs, EXPORT_RES, path.with_extension("obj"))
+                .and_then(|()| export::write_gltf(cs, EXPORT_RES, path.with_extension("gltf")));
+            match result {
+                Ok(()) => println!("Exported course to {}.obj and .gltf", path.display()),
+                Err(err) => println!("Failed to export course to {}: {}", path.display(), err)
             }
         }
     }
@@ -201,8 +359,16 @@ impl<'a, 'b> State<GameData<'a, 'b>, Event> for Hybrid {
         data: StateData<GameData>,
         event: Event
     ) -> Trans<GameData<'a, 'b>, Event> {
-        let mut events = data.world.write_resource::<Vec<Event>>();
-        events.push(event);
+        assign_pad(data.world, &event);
+
+        match event.event {
+            ButtonPressed(Button::Start, _) => return Trans::Push(Box::new(editor::Editor::new())),
+            ButtonPressed(Button::Select, _) => {
+                let mut overlay = data.world.write_resource::<DebugOverlay>();
+                overlay.enabled = !overlay.enabled;
+            },
+            _ => ()
+        };
 
         println!("{:?}", event);
         Trans::None
@@ -214,7 +380,70 @@ impl<'a, 'b> State<GameData<'a, 'b>, Event> for Hybrid {
     }
 }
 
-fn initialize_camera(world: &mut World, target: Entity) {
+fn spawn_player(world: &mut World, player: usize, pad: Option<usize>) -> Entity {
+    let (mesh, mtl) = {
+        let assets = world.read_resource::<BallAssets>();
+        (assets.mesh.clone(), assets.mtl.clone())
+    };
+
+    // Side by side, so players don't start on top of each other
+    let spawn = Vector3::new(5.0 + player as f32 * 3.0, 30.0, 5.0);
+    let mut trans = Transform::default();
+    trans.set_scale(0.3, 0.3, 0.3);
+    trans.set_position(spawn);
+
+    world
+        .create_entity()
+        .with(mesh)
+        .with(mtl)
+        .with(trans)
+        .with(DebugLinesComponent::new())
+        .with(Ball::new(spawn))
+        .with(PlayerInput::new(player, pad))
+        .build()
+}
+
+// Gives a pad that hasn't been seen before to a player still waiting for one,
+// or to a new player if it pressed a button and there is room.
+fn assign_pad(world: &mut World, event: &Event) {
+    let (known, waiting, players) = {
+        let entities = world.entities();
+        let inputs = world.read_storage::<PlayerInput>();
+
+        let known = inputs.join().any(|input| input.pad == Some(event.id));
+        let waiting = (&*entities, &inputs).join()
+            .find(|(_entity, input)| input.pad.is_none())
+            .map(|(entity, input)| (entity, input.player));
+        (known, waiting, inputs.join().count())
+    };
+    if known {
+        return
+    };
+
+    match waiting {
+        Some((entity, player)) => {
+            let mut inputs = world.write_storage::<PlayerInput>();
+            inputs.get_mut(entity).unwrap().pad = Some(event.id);
+            println!("Player {} is using pad {}", player + 1, event.id);
+        },
+        None => {
+            if let ButtonPressed(_, _) = event.event {
+                if players < MAX_PLAYERS {
+                    // The renderer only draws through the ActiveCamera, so rather than splitting the screen
+                    // everyone shares it, and it pulls back to keep every ball in view
+                    let ball = spawn_player(world, players, Some(event.id));
+                    let camera = world.read_resource::<ActiveCamera>().entity;
+                    if let Some(follow) = world.write_storage::<Follow>().get_mut(camera) {
+                        follow.entities.push(ball);
+                    };
+                    println!("Player {} joined with pad {}", players + 1, event.id);
+                }
+            }
+        }
+    }
+}
+
+fn initialize_camera(world: &mut World, target: Entity) -> Entity {
     let mut transform = Transform::default();
     transform.set_position(Vector3::new(0.0, 10.0, 100.0));
     //transform.set_rotation(Deg(90.0), Deg(0.0), Deg(0.0));
@@ -223,11 +452,10 @@ fn initialize_camera(world: &mut World, target: Entity) {
         .create_entity()
         .with(Camera::from(Projection::perspective(1.0, 60.0 * PI / 180.0)))
         .with(transform)
-        .with(Follow { entity: target })
-        .build();
+        .with(Follow { entities: vec![target] })
+        .build()
 }
 
-
 fn initialize_lights(world: &mut World) {
     world.add_resource(AmbientColor(Rgba(0.15, 0.18, 0.35, 1.0)));
     {
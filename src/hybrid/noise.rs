@@ -0,0 +1,63 @@
+// Deterministic hashing and value noise for terrain generation.
+// Everything here is a pure function of its inputs, so terrain can be regenerated piece by piece.
+
+// SplitMix64 finalizer, scrambles nearby inputs into unrelated outputs
+fn mix64(x: u64) -> u64 {
+    let x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    let x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^ (x >> 31)
+}
+
+/// Seed for the random numbers of a single grid position.
+pub fn cell_seed(seed: u64, i: i32, j: i32) -> u64 {
+    mix64(seed ^ mix64(((i as u32 as u64) << 32) | (j as u32 as u64)))
+}
+
+/// Random value in [0, 1) for a grid position.
+pub fn hash01(seed: u64, i: i32, j: i32) -> f32 {
+    // The top 24 bits fit exactly in an f32 mantissa
+    (cell_seed(seed, i, j) >> 40) as f32 / (1u64 << 24) as f32
+}
+
+fn smoothstep(t: f32) -> f32 {
+    t * t * (3.0 - 2.0 * t)
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+/// Smoothly interpolated value noise in [-1, 1], with features roughly one unit apart.
+pub fn value(seed: u64, x: f32, y: f32) -> f32 {
+    let (x0, y0) = (x.floor(), y.floor());
+    let (i, j) = (x0 as i32, y0 as i32);
+    let (tx, ty) = (smoothstep(x - x0), smoothstep(y - y0));
+
+    let corner = |di: i32, dj: i32| hash01(seed, i + di, j + dj) * 2.0 - 1.0;
+
+    lerp(
+        lerp(corner(0, 0), corner(1, 0), tx),
+        lerp(corner(0, 1), corner(1, 1), tx),
+        ty
+    )
+}
+
+/// Fractal sum of value noise octaves, each at double the frequency and half the amplitude. In [-1, 1].
+pub fn fbm(seed: u64, x: f32, y: f32, octaves: u32) -> f32 {
+    let mut sum = 0.0;
+    let mut amplitude = 1.0;
+    let mut frequency = 1.0;
+    let mut total = 0.0;
+    for octave in 0..octaves {
+        sum += amplitude * value(mix64(seed.wrapping_add(octave as u64)), x * frequency, y * frequency);
+        total += amplitude;
+        amplitude *= 0.5;
+        frequency *= 2.0;
+    };
+    sum / total
+}
+
+/// Noise with sharp crests where fbm crosses zero, in [-1, 1]. Good for ridges and gullies.
+pub fn ridged(seed: u64, x: f32, y: f32, octaves: u32) -> f32 {
+    1.0 - 2.0 * fbm(seed, x, y, octaves).abs()
+}
@@ -0,0 +1,206 @@
+// Rigid body model of the ball, a solid sphere rolling and bouncing on the bezier surface.
+// Everything is in world units and seconds, and step only depends on its arguments,
+// so the same inputs always give the same trajectory.
+
+use glm;
+
+/// Physical properties of a ball
+#[derive(Clone, Copy, Debug)]
+pub struct BallParams {
+    pub radius: f32,
+    pub mass: f32,
+    /// Downwards acceleration, in world units per second squared
+    pub gravity: f32,
+    /// Quadratic air drag coefficient
+    pub drag: f32,
+    /// Fraction of the normal speed kept when bouncing
+    pub restitution: f32,
+    /// Impacts slower than this don't bounce, so the ball can settle onto the surface
+    pub bounce_threshold: f32,
+    /// Coefficient of friction keeping the ball rolling rather than sliding
+    pub static_friction: f32,
+    /// Coefficient of friction while sliding
+    pub kinetic_friction: f32,
+    /// Coefficient of rolling resistance
+    pub rolling_resistance: f32,
+    /// Speed a jump adds away from the surface
+    pub jump_speed: f32
+}
+
+impl Default for BallParams {
+    fn default() -> Self {
+        BallParams {
+            radius: 0.5,
+            mass: 80.0,
+            gravity: 9.81,
+            drag: 0.25,
+            restitution: 0.3,
+            bounce_threshold: 2.0,
+            static_friction: 0.6,
+            kinetic_friction: 0.3,
+            rolling_resistance: 0.02,
+            jump_speed: 6.0
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct BallState {
+    pub position: glm::Vec3,
+    pub velocity: glm::Vec3
+}
+
+impl BallState {
+    pub fn new(position: glm::Vec3) -> Self {
+        BallState {
+            position,
+            velocity: glm::vec3(0.0, 0.0, 0.0)
+        }
+    }
+}
+
+/// The closest point of the surface under (or above) the ball
+#[derive(Clone, Copy, Debug)]
+pub struct Contact {
+    pub point: glm::Vec3,
+    pub normal: glm::Vec3
+}
+
+// Distance beyond touching at which the ball still counts as resting on the surface
+const CONTACT_SKIN: f32 = 0.05;
+// Speed away from the surface past which a ball within the skin is leaving it, rather than resting on it
+const LIFT_OFF_SPEED: f32 = 0.1;
+
+impl Contact {
+    /// How far the ball's surface is above the contact point, negative when sunk into it.
+    pub fn gap(&self, state: &BallState, params: &BallParams) -> f32 {
+        glm::dot(&(state.position - self.point), &self.normal) - params.radius
+    }
+
+    pub fn touching(&self, state: &BallState, params: &BallParams) -> bool {
+        self.gap(state, params) <= CONTACT_SKIN
+    }
+
+    /// Whether the ball is on its way off the surface, after bouncing or jumping.
+    pub fn leaving(&self, state: &BallState, params: &BallParams) -> bool {
+        self.gap(state, params) >= 0.0 && glm::dot(&state.velocity, &self.normal) > LIFT_OFF_SPEED
+    }
+}
+
+/// Launches the ball off the surface if it is touching it. Returns whether it jumped.
+pub fn jump(state: &mut BallState, params: &BallParams, contact: Option<&Contact>) -> bool {
+    match contact {
+        Some(contact) if contact.touching(state, params) => {
+            state.velocity += contact.normal * params.jump_speed;
+            true
+        },
+        _ => false
+    }
+}
+
+/// Advances the ball by dt seconds. Thrust is an acceleration from the player, and only acts along
+/// the surface while touching it. Returns the speed the ball hit the surface with, if it did.
+pub fn step(state: &mut BallState, params: &BallParams, contact: Option<&Contact>, thrust: glm::Vec3, dt: f32) -> Option<f32> {
+    let mut accel = glm::vec3(0.0, -params.gravity, 0.0);
+
+    let speed = glm::length(&state.velocity);
+    if speed > 0.0 {
+        accel -= state.velocity * (params.drag * speed / params.mass);
+    };
+
+    let mut impact = None;
+
+    match contact {
+        // A ball leaving the surface flies freely, even while it is still within the skin
+        Some(contact) if contact.touching(state, params) && !contact.leaving(state, params) => {
+            let normal = contact.normal;
+
+            // Push the ball back out of the surface, or pull it down onto it from within the skin,
+            // so it rests on the surface rather than hovering above it
+            let gap = contact.gap(state, params);
+            state.position -= normal * gap;
+
+            // Bounce off, or come to rest on, the surface. A ball too slow to be leaving it doesn't drift off it either.
+            let normal_speed = glm::dot(&state.velocity, &normal);
+            if normal_speed < 0.0 {
+                impact = Some(-normal_speed);
+            };
+            let bounce = if -normal_speed > params.bounce_threshold { params.restitution } else { 0.0 };
+            state.velocity -= normal * normal_speed * (1.0 + bounce);
+
+            let thrust = thrust - normal * glm::dot(&thrust, &normal);
+            accel += thrust;
+
+            // The normal force cancels whatever pushes into the surface, per unit mass
+            let into = glm::dot(&accel, &normal);
+            let normal_force = if into < 0.0 { -into } else { 0.0 };
+            accel += normal * normal_force;
+
+            // A solid sphere (I = 2/5 m r^2) rolling without slipping only gets 5/7 of the push along
+            // the surface, the rest turns it. That takes 2/7 of the push in static friction,
+            // past which it slides instead.
+            let along = accel - normal * glm::dot(&accel, &normal);
+            let tangent_velocity = state.velocity - normal * glm::dot(&state.velocity, &normal);
+            let rolling = glm::length(&along) * (2.0 / 7.0) <= params.static_friction * normal_force;
+
+            let resistance = if rolling {
+                accel -= along * (2.0 / 7.0);
+                params.rolling_resistance * normal_force
+            } else {
+                params.kinetic_friction * normal_force
+            };
+
+            // Friction can stop the ball but never push it backwards
+            let tangent_speed = glm::length(&tangent_velocity);
+            if tangent_speed > 0.0 {
+                let resistance = resistance.min(tangent_speed / dt);
+                accel -= tangent_velocity * (resistance / tangent_speed);
+            };
+        },
+        _ => ()
+    };
+
+    // Semi-implicit Euler
+    state.velocity += accel * dt;
+    state.position += state.velocity * dt;
+
+    impact
+}
+
+/// Length of a physics step in seconds, the simulation always advances by whole steps
+pub const TIMESTEP: f32 = 1.0 / 120.0;
+
+// After a long stall, drop the time that is left over rather than trying to catch up all at once
+const MAX_STEPS: u32 = 8;
+
+/// Accumulates frame time and hands it out as whole physics steps.
+#[derive(Default)]
+pub struct FixedStep {
+    accumulator: f32
+}
+
+impl FixedStep {
+    /// Adds a frame's time, and returns how many steps to simulate for it.
+    pub fn advance(&mut self, dt: f32) -> u32 {
+        self.accumulator += dt;
+        let mut steps = 0;
+        while self.accumulator >= TIMESTEP && steps < MAX_STEPS {
+            self.accumulator -= TIMESTEP;
+            steps += 1;
+        };
+        if steps == MAX_STEPS {
+            self.accumulator = self.accumulator.min(TIMESTEP);
+        };
+        steps
+    }
+
+    /// How far between the last two steps the current frame is, from 0 to 1.
+    pub fn alpha(&self) -> f32 {
+        (self.accumulator / TIMESTEP).min(1.0)
+    }
+}
+
+/// Position to render the ball at, between the last two physics states.
+pub fn interpolate(previous: &BallState, current: &BallState, alpha: f32) -> glm::Vec3 {
+    glm::mix(&previous.position, &current.position, alpha)
+}
@@ -4,13 +4,51 @@ use amethyst::{
     core::nalgebra::{Vector2, Vector3}
 };
 
-use rand::{thread_rng, Rng};
+use rand::{thread_rng, Rng, SeedableRng};
+use rand::prng::ChaChaRng;
 
 use glm;
-use nalgebra::geometry::{Point2, Point3};
+use image::GrayImage;
+use ron;
+use nalgebra::{Rotation3, UnitQuaternion};
+use nalgebra::geometry::{Point2, Point3, Isometry3};
+use nalgebra::Matrix3;
 use ncollide3d::shape::{TriMesh};
+use ncollide3d::query::{Ray, RayCast};
 
-#[derive(Clone, Copy)]
+use super::noise::{self, cell_seed};
+
+// Course files store glm vectors and quaternions as plain arrays
+mod vec3_format {
+    use glm;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(v: &glm::Vec3, serializer: S) -> Result<S::Ok, S::Error> {
+        [v.x, v.y, v.z].serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<glm::Vec3, D::Error> {
+        let [x, y, z] = <[f32; 3]>::deserialize(deserializer)?;
+        Ok(glm::vec3(x, y, z))
+    }
+}
+
+mod quat_format {
+    use glm;
+    use nalgebra::Quaternion;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(q: &glm::Quat, serializer: S) -> Result<S::Ok, S::Error> {
+        [q.coords.x, q.coords.y, q.coords.z, q.coords.w].serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<glm::Quat, D::Error> {
+        let [x, y, z, w] = <[f32; 4]>::deserialize(deserializer)?;
+        Ok(Quaternion::new(w, x, y, z))
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
 pub enum Dir8 {
     North,
     NorthEast,
@@ -23,9 +61,16 @@ pub enum Dir8 {
 }
 use self::Dir8::*;
 
-#[derive(Clone, Copy)]
+impl Dir8 {
+    /// Every direction, clockwise from North
+    pub const ALL: [Dir8; 8] = [North, NorthEast, East, SouthEast, South, SouthWest, West, NorthWest];
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
 pub struct ControlPlane {
+    #[serde(with = "vec3_format")]
     pos: glm::Vec3,
+    #[serde(with = "quat_format")]
     rotation: glm::Quat,
     north: f32,
     east: f32,
@@ -46,6 +91,81 @@ impl ControlPlane {
         }
     }
 
+    /// A randomly oriented plane at grid position (i, j). The plane only depends on the seed
+    /// and its position, so any part of an endless terrain can be reproduced on its own.
+    pub fn procedural(seed: u64, i: i32, j: i32) -> Self {
+        let mut rng = ChaChaRng::seed_from_u64(cell_seed(seed, i, j));
+        let mut control = ControlPlane::new();
+
+        let q = control.rotation;
+        let q = glm::quat_rotate_normalized_axis(&q, rng.gen_range(-0.7, 0.7), &glm::vec3(0.0, 0.0, 1.0));
+        let q = glm::quat_rotate_normalized_axis(&q, rng.gen_range(-1.2, 1.2), &glm::vec3(0.0, 1.0, 0.0));
+        let q = glm::quat_rotate_normalized_axis(&q, rng.gen_range(-0.7, 0.7), &glm::vec3(1.0, 0.0, 0.0));
+
+        control.rotation = q;
+        control.pos = glm::vec3(i as f32, rng.gen_range(-0.8, 0.8), j as f32);
+
+        //if i == 3 && j == 3 {
+        //    control.pos.y = -0.7;
+        //    control.south = 0.8;
+        //    control.west = -0.9;
+        //};
+        control
+    }
+
+    /// A plane at pos lying flat on a height field with the given gradient (dy/dx, dy/dz),
+    /// its handles reaching about a third of the way to the neighbouring control planes.
+    pub fn on_slope(pos: glm::Vec3, gradient: glm::Vec2) -> Self {
+        let normal = glm::vec3(-gradient.x, 1.0, -gradient.y).normalize();
+        // Local x follows the slope towards East, which is always perpendicular to the normal
+        let east = glm::vec3(1.0, gradient.x, 0.0).normalize();
+        let north = east.cross(&normal);
+
+        let rotation = Rotation3::from_matrix_unchecked(glm::mat3(
+            east.x, normal.x, north.x,
+            east.y, normal.y, north.y,
+            east.z, normal.z, north.z
+        ));
+
+        let extent_x = (1.0 + gradient.x * gradient.x).sqrt() / 3.0;
+        let extent_z = (1.0 + gradient.y * gradient.y).sqrt() / 3.0;
+
+        ControlPlane {
+            pos,
+            rotation: *UnitQuaternion::from_rotation_matrix(&rotation).quaternion(),
+            north: extent_z,
+            east: extent_x,
+            south: -extent_z,
+            west: -extent_x
+        }
+    }
+
+    pub fn pos(&self) -> glm::Vec3 {
+        self.pos
+    }
+
+    pub fn translate(&mut self, delta: &glm::Vec3) {
+        self.pos += delta;
+    }
+
+    /// Rotate the plane about an axis in its own frame.
+    pub fn rotate(&mut self, angle: f32, axis: &glm::Vec3) {
+        self.rotation = glm::quat_rotate_normalized_axis(&self.rotation, angle, axis);
+    }
+
+    /// Pull the handle in the given direction (North, East, South or West) outwards, or push it in
+    /// with a negative amount. Handles can't be pushed past the center.
+    pub fn extend(&mut self, dir: Dir8, amount: f32) {
+        const MIN_EXTENT: f32 = 0.01;
+        match dir {
+            North => self.north = (self.north + amount).max(MIN_EXTENT),
+            East => self.east = (self.east + amount).max(MIN_EXTENT),
+            South => self.south = (self.south - amount).min(-MIN_EXTENT),
+            West => self.west = (self.west - amount).min(-MIN_EXTENT),
+            _ => ()
+        }
+    }
+
     pub fn point(&self, dir : Dir8) -> glm::Vec3 {
         let (x, y) = match dir {
             North => (0.0, self.north),
@@ -98,12 +218,75 @@ fn fac(n: i32) -> i32 {
     m
 }
 
-fn binomial3(i: i32) -> f32 {
-    6.0 / (fac(i) * fac(3 - i)) as f32
+fn binomial(n: i32, i: i32) -> f32 {
+    (fac(n) / (fac(i) * fac(n - i))) as f32
+}
+
+// Bernstein basis polynomial of degree n, zero outside of 0 <= i <= n
+fn bernstein(n: i32, i: i32, u: f32) -> f32 {
+    if i < 0 || i > n {
+        0.0
+    } else {
+        binomial(n, i) * u.powi(i) * (1.0 - u).powi(n - i)
+    }
 }
 
 fn bernstein3(i: i32, u: f32) -> f32 {
-    binomial3(i) * u.powi(i) * (1.0 - u).powi(3 - i)
+    bernstein(3, i, u)
+}
+
+// First derivative of the cubic Bernstein polynomial
+fn bernstein3_d1(i: i32, u: f32) -> f32 {
+    3.0 * (bernstein(2, i - 1, u) - bernstein(2, i, u))
+}
+
+// Second derivative of the cubic Bernstein polynomial
+fn bernstein3_d2(i: i32, u: f32) -> f32 {
+    6.0 * (bernstein(1, i - 2, u) - 2.0 * bernstein(1, i - 1, u) + bernstein(1, i, u))
+}
+
+fn clamp(n: f32) -> f32 {
+    if n <= 0.0 {
+        0.0
+    } else if n >= 1.0 {
+        1.0
+    } else {
+        n
+    }
+}
+
+/// Principal curvature measures of the surface at a point.
+#[derive(Clone, Copy, Debug)]
+pub struct Curvature {
+    pub gaussian: f32,
+    pub mean: f32
+}
+
+/// A triangle mesh with shared vertices.
+pub struct IndexedMesh<A> {
+    pub vertices: Vec<A>,
+    pub indices: Vec<[usize; 3]>
+}
+
+impl<A: Clone> IndexedMesh<A> {
+    /// Expand into a plain triangle list, for consumers without index buffer support.
+    /// The render meshes are built this way, as amethyst's MeshData only takes a vertex list.
+    pub fn unindexed(&self) -> Vec<A> {
+        self.indices.iter()
+            .flat_map(|triangle| triangle.iter())
+            .map(|i| self.vertices[*i].clone())
+            .collect()
+    }
+}
+
+/// The intersection of a ray with the bezier surface.
+#[derive(Clone, Copy, Debug)]
+pub struct SurfaceHit {
+    pub u: f32,
+    pub v: f32,
+    pub toi: f32,
+    pub point: glm::Vec3,
+    pub normal: glm::Vec3
 }
 
 impl BicubicPatch {
@@ -137,124 +320,595 @@ impl BicubicPatch {
 
     }
 
+    /// The 4 * 4 control points, rows going East and columns going North.
+    pub fn control_net(&self) -> &[[glm::Vec3; 4]; 4] {
+        &self.controls
+    }
+
     fn control(&self, i: i32, j: i32) -> glm::Vec3 {
         self.controls[i as usize][j as usize]
     }
     
-    fn pos(&self, u: f32, v: f32) -> glm::Vec3 {
+    // Sum the control points weighted by the given basis functions in u and v
+    fn eval<F, G>(&self, u: f32, v: f32, fu: F, fv: G) -> glm::Vec3
+    where F: Fn(i32, f32) -> f32, G: Fn(i32, f32) -> f32 {
         // Only defined for the unit square
         assert!(0.0 <= u && u <= 1.0 && 0.0 <= v && v <= 1.0);
-        
+
         let mut sum = glm::vec3(0.0, 0.0, 0.0);
         for i in 0..4 {
             for j in 0..4 {
-                sum += (bernstein3(i, u) * bernstein3(j, v)) * self.control(i, j)
+                sum += (fu(i, u) * fv(j, v)) * self.control(i, j)
             }
         };
         sum
     }
 
-    pub fn normal(&self, res: i32, u: f32, v: f32) -> glm::Vec3 {
-        let p = self.pos(u, v);
+    pub fn pos(&self, u: f32, v: f32) -> glm::Vec3 {
+        self.eval(u, v, bernstein3, bernstein3)
+    }
+
+    /// Partial derivative of the surface with respect to u.
+    pub fn du(&self, u: f32, v: f32) -> glm::Vec3 {
+        self.eval(u, v, bernstein3_d1, bernstein3)
+    }
+
+    /// Partial derivative of the surface with respect to v.
+    pub fn dv(&self, u: f32, v: f32) -> glm::Vec3 {
+        self.eval(u, v, bernstein3, bernstein3_d1)
+    }
+
+    fn duu(&self, u: f32, v: f32) -> glm::Vec3 {
+        self.eval(u, v, bernstein3_d2, bernstein3)
+    }
+
+    fn duv(&self, u: f32, v: f32) -> glm::Vec3 {
+        self.eval(u, v, bernstein3_d1, bernstein3_d1)
+    }
+
+    fn dvv(&self, u: f32, v: f32) -> glm::Vec3 {
+        self.eval(u, v, bernstein3, bernstein3_d2)
+    }
+
+    /// Unit tangents along u (towards East) and v (towards North).
+    pub fn tangents(&self, u: f32, v: f32) -> (glm::Vec3, glm::Vec3) {
+        (self.du(u, v).normalize(), self.dv(u, v).normalize())
+    }
+
+    /// The exact surface normal, oriented upwards.
+    pub fn normal(&self, u: f32, v: f32) -> glm::Vec3 {
+        let n = self.dv(u, v).cross(&self.du(u, v));
+
+        // A degenerate control net can collapse a derivative, fall back to straight up
+        if n.magnitude() <= 1e-6 {
+            glm::vec3(0.0, 1.0, 0.0)
+        } else {
+            n.normalize()
+        }
+    }
+
+    /// Gaussian and mean curvature, from the first and second fundamental forms.
+    pub fn curvature(&self, u: f32, v: f32) -> Curvature {
+        let p_u = self.du(u, v);
+        let p_v = self.dv(u, v);
+        let n = self.normal(u, v);
+
+        let e1 = p_u.dot(&p_u);
+        let f1 = p_u.dot(&p_v);
+        let g1 = p_v.dot(&p_v);
+
+        let e2 = self.duu(u, v).dot(&n);
+        let f2 = self.duv(u, v).dot(&n);
+        let g2 = self.dvv(u, v).dot(&n);
+
+        let det = e1 * g1 - f1 * f1;
+        if det.abs() <= 1e-12 {
+            return Curvature { gaussian: 0.0, mean: 0.0 }
+        };
+
+        Curvature {
+            gaussian: (e2 * g2 - f2 * f2) / det,
+            mean: (e2 * g1 - 2.0 * f2 * f1 + g2 * e1) / (2.0 * det)
+        }
+    }
+
+    /// Intersect a ray with the patch scaled by `scale`. The collision mesh gives a first guess
+    /// which is then refined with Newton iteration on the bicubic surface itself.
+    pub fn ray_intersect(&self, collision_mesh: &TriMesh<f32>, scale: f32, ray: &Ray<f32>) -> Option<SurfaceHit> {
+        const ITERATIONS: usize = 8;
+        const TOLERANCE: f32 = 1e-5;
+
+        let hit = collision_mesh.toi_and_normal_and_uv_with_ray(&Isometry3::identity(), ray, false)?;
+        let uv = hit.uvs?;
+        let (mesh_u, mesh_v) = (clamp(uv.x), clamp(uv.y));
+
+        // Solve scale * P(u, v) = origin + t * dir for (u, v, t)
+        let (mut u, mut v, mut t) = (mesh_u, mesh_v, hit.toi);
+        let mut converged = false;
+        for _ in 0..ITERATIONS {
+            let r = self.pos(u, v) * scale - (ray.origin.coords + ray.dir * t);
+            let jacobian = Matrix3::from_columns(&[self.du(u, v) * scale, self.dv(u, v) * scale, -ray.dir]);
+            let step = match jacobian.try_inverse() {
+                Some(inverse) => inverse * r,
+                None => break
+            };
+
+            u = clamp(u - step.x);
+            v = clamp(v - step.y);
+            t = t - step.z;
+
+            // The residual is in world units, which lose precision far from the origin on endless terrain,
+            // so convergence is judged by how far the step moved (u, v, t) instead
+            if step.x.abs() <= TOLERANCE && step.y.abs() <= TOLERANCE && step.z.abs() <= TOLERANCE * scale {
+                converged = true;
+                break
+            };
+        };
+
+        // If Newton fails to converge (e.g. the ray grazes the surface) fall back to the mesh hit
+        if !converged || t < 0.0 {
+            u = mesh_u;
+            v = mesh_v;
+            t = hit.toi;
+        };
+
+        Some(SurfaceHit {
+            u,
+            v,
+            toi: t,
+            point: self.pos(u, v) * scale,
+            normal: self.normal(u, v)
+        })
+    }
 
-        // Compute a normal using two nearby points
-        let delta_u = u - (0.5 / res as f32);
-        let delta_v = v - (0.5 / res as f32);
-        let p_u = self.pos(if delta_u < 0.0 { u + (0.5 / res as f32) } else { delta_u }, v);
-        let p_v = self.pos(u, if delta_v < 0.0 { v + (0.5 / res as f32) } else { delta_v });
+    // Position and normal of grid point (row, col), snapped onto the coarser edge tessellation where needed
+    fn stitched_point(&self, res: i32, edges: [i32; 4], row: i32, col: i32) -> (glm::Vec3, glm::Vec3) {
+        let [north, east, south, west] = edges;
+        let point = |row: i32, col: i32| {
+            let (u, v) = (row as f32 / res as f32, col as f32 / res as f32);
+            (self.pos(u, v), self.normal(u, v))
+        };
+        // Interpolate between the two coarse vertices either side of k along an edge
+        let snap = |k: i32, edge_res: i32, along: &Fn(i32) -> (glm::Vec3, glm::Vec3)| {
+            let step = res / edge_res;
+            if k % step == 0 {
+                along(k)
+            } else {
+                let k0 = (k / step) * step;
+                let t = (k - k0) as f32 / step as f32;
+                let (p0, n0) = along(k0);
+                let (p1, n1) = along(k0 + step);
+                (glm::lerp(&p0, &p1, t), glm::lerp(&n0, &n1, t).normalize())
+            }
+        };
 
-        // We need to flip some normals near the edge
-        if (delta_u < 0.0) && (delta_v >= 0.0) || (delta_u >= 0.0) && (delta_v < 0.0) {
-            (p - p_u).cross(&(p - p_v)).normalize()
+        if col == res && north < res {
+            snap(row, north, &|k| point(k, col))
+        } else if row == res && east < res {
+            snap(col, east, &|k| point(row, k))
+        } else if col == 0 && south < res {
+            snap(row, south, &|k| point(k, col))
+        } else if row == 0 && west < res {
+            snap(col, west, &|k| point(row, k))
         } else {
-            (p - p_u).cross(&(p - p_v)).normalize() * -1.0
+            point(row, col)
         }
     }
 
     // Rasterize the patch into a res * res grid
     pub fn rasterize_with<F, A>(&self, res: i32, f : F) -> Vec<A>
+    where F: Fn(glm::Vec3, glm::Vec3, f32, f32) -> A, A: Clone {
+        self.rasterize_stitched_with(res, [res; 4], f)
+    }
+
+    /// Rasterize the patch into a res * res grid, where the north, east, south and west edges
+    /// are tessellated with the given (coarser) resolutions so they line up with neighbouring patches.
+    /// Every resolution must divide res.
+    ///
+    /// Returns 6 vertices per grid square, for render meshes. Each grid point is still only evaluated
+    /// once, but the vertices are copied out of the indexed grid since MeshData has no index buffer.
+    pub fn rasterize_stitched_with<F, A>(&self, res: i32, edges: [i32; 4], f : F) -> Vec<A>
+    where F: Fn(glm::Vec3, glm::Vec3, f32, f32) -> A, A: Clone {
+        self.rasterize_indexed_with(res, edges, f).unindexed()
+    }
+
+    /// Like rasterize_stitched_with, but each of the (res + 1) * (res + 1) grid points is evaluated
+    /// once and shared between the triangles using it.
+    pub fn rasterize_indexed_with<F, A>(&self, res: i32, edges: [i32; 4], f : F) -> IndexedMesh<A>
     where F: Fn(glm::Vec3, glm::Vec3, f32, f32) -> A {
-        let mut vec = Vec::new();
-        for row in 0..res {
-            for col in 0..res {
-                // Generate two triangles for each square in the grid
-                for (rt, ct) in [(0.0, 0.0), (0.0, 1.0), (1.0, 1.0), (0.0, 0.0), (1.0, 1.0), (1.0, 0.0)].iter() {
-                    let u = (row as f32 + rt) / res as f32;
-                    let v = (col as f32 + ct) / res as f32;
+        assert!(edges.iter().all(|edge_res| *edge_res > 0 && res % edge_res == 0));
 
-                    let p = self.pos(u, v);
-                    let normal = self.normal(32, u, v);
+        let stride = (res + 1) as usize;
+        let mut vertices = Vec::with_capacity(stride * stride);
+        for row in 0..(res + 1) {
+            for col in 0..(res + 1) {
+                let u = row as f32 / res as f32;
+                let v = col as f32 / res as f32;
 
-                    vec.push(f(p, normal, u, v))
-                }
+                let (p, normal) = self.stitched_point(res, edges, row, col);
+
+                vertices.push(f(p, normal, u, v))
             }
         };
-        vec
+
+        let mut indices = Vec::with_capacity((res * res * 2) as usize);
+        for row in 0..(res as usize) {
+            for col in 0..(res as usize) {
+                let index = |rt: usize, ct: usize| (row + rt) * stride + col + ct;
+
+                // Two triangles for each square in the grid
+                indices.push([index(0, 0), index(0, 1), index(1, 1)]);
+                indices.push([index(0, 0), index(1, 1), index(1, 0)]);
+            }
+        };
+
+        IndexedMesh { vertices, indices }
     }
 
     pub fn rasterize(&self, res: i32) -> Vec<PosNormTex> {
-        self.rasterize_with(res, |p, n, u, v| PosNormTex { position: p, normal: n, tex_coord: Vector2::new(u, v) })
+        self.rasterize_stitched(res, [res; 4])
+    }
+
+    pub fn rasterize_stitched(&self, res: i32, edges: [i32; 4]) -> Vec<PosNormTex> {
+        self.rasterize_stitched_with(res, edges, |p, n, u, v| PosNormTex { position: p, normal: n, tex_coord: Vector2::new(u, v) })
     }
 
     // TODO: pass a more general translation + scale here
     pub fn collision_mesh(&self, res: i32, scale: f32) -> TriMesh<f32> {
-        let mut points = Vec::new();
-        let mut i: usize = 0;
-        let mut indices = Vec::new();
-        let mut uvs = Vec::new();
+        let mesh = self.rasterize_indexed_with(res, [res; 4], |p, _, u, v| (p, u, v));
 
-        for (p, u, v) in self.rasterize_with(res, |p, _, u, v| (p, u, v)) {
+        let mut points = Vec::with_capacity(mesh.vertices.len());
+        let mut uvs = Vec::with_capacity(mesh.vertices.len());
+        for (p, u, v) in mesh.vertices {
             points.push(Point3::new(scale * p.x, scale * p.y, scale * p.z));
-            if i % 3 == 0 {
-                indices.push(Point3::new(i, i + 1, i + 2));
-            };
-            i = i + 1;
             uvs.push(Point2::new(u, v))
         };
+        let indices = mesh.indices.iter()
+            .map(|[a, b, c]| Point3::new(*a, *b, *c))
+            .collect();
 
         TriMesh::new(points, indices, Some(uvs))
     }
 }
 
-const SIZE: usize = 64;
+/// How the control planes of a procedural terrain are laid out.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub enum Profile {
+    /// Randomly tilted planes scattered around a flat field
+    Bumpy,
+    /// A mountainside to ride down
+    Downhill(Downhill)
+}
+
+impl Profile {
+    pub fn plane(&self, seed: u64, i: i32, j: i32) -> ControlPlane {
+        match *self {
+            Profile::Bumpy => ControlPlane::procedural(seed, i, j),
+            Profile::Downhill(ref downhill) => downhill.plane(seed, i, j)
+        }
+    }
+}
+
+/// A slope falling away along the fall line, with ridges and gullies running down it and the occasional jump.
+/// Distances are in control planes.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct Downhill {
+    /// Drop in height per control plane along the fall line
+    pub slope: f32,
+    /// Direction of the fall line in radians, from East (+x) towards North (+z)
+    pub fall_line: f32,
+    /// Height of the ridges above the gullies
+    pub ridges: f32,
+    /// Distance between ridges across the fall line
+    pub ridge_spacing: f32,
+    /// Height of the small bumps all over the slope
+    pub roughness: f32,
+    /// Fraction of control planes that form the lip of a jump
+    pub jumps: f32,
+    pub jump_height: f32,
+    /// How far a jump's lip is kicked up against the fall line, in radians
+    pub jump_angle: f32
+}
+
+impl Default for Downhill {
+    fn default() -> Self {
+        Downhill {
+            slope: 0.35,
+            fall_line: 0.0,
+            ridges: 1.2,
+            ridge_spacing: 6.0,
+            roughness: 0.25,
+            jumps: 0.01,
+            jump_height: 0.6,
+            jump_angle: 0.5
+        }
+    }
+}
+
+impl Downhill {
+    // Height of the slope at a point on the grid, not counting jumps
+    fn height(&self, seed: u64, x: f32, z: f32) -> f32 {
+        let (sin, cos) = self.fall_line.sin_cos();
+        let along = x * cos + z * sin;
+        let across = z * cos - x * sin;
+
+        // Stretched along the fall line, so ridges and gullies run downhill
+        let ridges = noise::ridged(seed, across / self.ridge_spacing, along / (4.0 * self.ridge_spacing), 3);
+        let bumps = noise::fbm(seed.wrapping_add(1), x * 0.5, z * 0.5, 2);
+
+        -self.slope * along + self.ridges * ridges + self.roughness * bumps
+    }
+
+    pub fn plane(&self, seed: u64, i: i32, j: i32) -> ControlPlane {
+        let (x, z) = (i as f32, j as f32);
+        let mut y = self.height(seed, x, z);
+
+        // Lie the plane flat on the slope, using the gradient of the height field
+        const H: f32 = 0.05;
+        let dx = (self.height(seed, x + H, z) - self.height(seed, x - H, z)) / (2.0 * H);
+        let dz = (self.height(seed, x, z + H) - self.height(seed, x, z - H)) / (2.0 * H);
+        let mut normal = glm::vec3(-dx, 1.0, -dz).normalize();
+
+        // Jumps raise the plane and tip it back uphill to form a lip
+        if noise::hash01(seed.wrapping_add(2), i, j) < self.jumps {
+            let (sin, cos) = self.fall_line.sin_cos();
+            normal = glm::rotate_vec3(&normal, self.jump_angle, &glm::vec3(-sin, 0.0, cos));
+            y += self.jump_height;
+        };
+
+        let tilt = UnitQuaternion::rotation_between(&glm::vec3(0.0, 1.0, 0.0), &normal)
+            .map(|q| *q.quaternion())
+            .unwrap_or_else(glm::quat_identity);
+        // A little variation in heading about the plane's own normal
+        let yaw = (noise::hash01(seed.wrapping_add(3), i, j) - 0.5) * 0.6;
+
+        let mut control = ControlPlane::new();
+        control.rotation = glm::quat_rotate_normalized_axis(&tilt, yaw, &glm::vec3(0.0, 1.0, 0.0));
+        control.pos = glm::vec3(x, y, z);
+        control
+    }
+}
 
+/// A grid of control planes, each neighbouring four form a bicubic patch.
+#[derive(Clone, Serialize, Deserialize)]
 pub struct ControlSurface {
-    pub controls: [[ControlPlane; SIZE]; SIZE]
+    width: usize,
+    height: usize,
+    controls: Vec<ControlPlane>
 }
 
 impl ControlSurface {
-    pub fn new() -> Self {
-        let mut surface = [[ControlPlane::new(); SIZE]; SIZE];
-        for i in 0..SIZE {
-            for j in 0..SIZE {
-                let mut rng = thread_rng();
-                let q = surface[i][j].rotation;
-                let q = glm::quat_rotate_normalized_axis(&q, rng.gen_range(-0.7, 0.7), &glm::vec3(0.0, 0.0, 1.0));
-                let q = glm::quat_rotate_normalized_axis(&q, rng.gen_range(-1.2, 1.2), &glm::vec3(0.0, 1.0, 0.0));
-                let q = glm::quat_rotate_normalized_axis(&q, rng.gen_range(-0.7, 0.7), &glm::vec3(1.0, 0.0, 0.0));
-
-                surface[i][j].rotation = q;
-                surface[i][j].pos = glm::vec3(i as f32, rng.gen_range(-0.8, 0.8), j as f32);
-
-                //if i == 3 && j == 3 {
-                //    surface[i][j].pos.y = -0.7;
-                //    surface[i][j].south = 0.8;
-                //    surface[i][j].west = -0.9;
-                //};
+    /// Create a surface of width * height control planes from a random seed.
+    pub fn new(width: usize, height: usize) -> Self {
+        ControlSurface::from_seed(width, height, thread_rng().gen())
+    }
+
+    /// Create a surface from a seed. The same seed always yields the same control planes,
+    /// matching those of the endless terrain with that seed.
+    pub fn from_seed(width: usize, height: usize, seed: u64) -> Self {
+        ControlSurface::from_profile(width, height, seed, &Profile::Bumpy)
+    }
+
+    /// Create a surface from a seed, laid out according to the profile.
+    pub fn from_profile(width: usize, height: usize, seed: u64, profile: &Profile) -> Self {
+        ControlSurface::from_fn(width, height, |i, j| profile.plane(seed, i as i32, j as i32))
+    }
+
+    /// Create a surface with the control plane at (i, j) given by f.
+    pub fn from_fn<F>(width: usize, height: usize, f: F) -> Self
+    where F: Fn(usize, usize) -> ControlPlane {
+        assert!(width >= 2 && height >= 2, "A control surface needs at least 2x2 control planes");
+
+        let mut controls = Vec::with_capacity(width * height);
+        for i in 0..width {
+            for j in 0..height {
+                controls.push(f(i, j));
             }
         };
-        ControlSurface { controls: surface }
+        ControlSurface { width, height, controls }
+    }
+
+    /// Create a surface of width * height control planes following a grayscale heightmap, where black is
+    /// at height 0 and white at height_scale. The top of the image is North.
+    pub fn from_heightmap(image: &GrayImage, width: usize, height: usize, height_scale: f32) -> Self {
+        let (image_width, image_height) = image.dimensions();
+
+        // Bilinearly sample the image at a point on the grid of control planes
+        let sample = |x: f32, z: f32| {
+            let px = (x / (width - 1) as f32).max(0.0).min(1.0) * (image_width - 1) as f32;
+            let py = (1.0 - z / (height - 1) as f32).max(0.0).min(1.0) * (image_height - 1) as f32;
+
+            let (x0, y0) = (px.floor() as u32, py.floor() as u32);
+            let (x1, y1) = ((x0 + 1).min(image_width - 1), (y0 + 1).min(image_height - 1));
+            let (tx, ty) = (px - x0 as f32, py - y0 as f32);
+
+            let luma = |x, y| image.get_pixel(x, y).data[0] as f32 / 255.0;
+            let top = luma(x0, y0) * (1.0 - tx) + luma(x1, y0) * tx;
+            let bottom = luma(x0, y1) * (1.0 - tx) + luma(x1, y1) * tx;
+            (top * (1.0 - ty) + bottom * ty) * height_scale
+        };
+
+        ControlSurface::from_fn(width, height, |i, j| {
+            let (x, z) = (i as f32, j as f32);
+            let gradient = glm::vec2(
+                (sample(x + 0.5, z) - sample(x - 0.5, z)),
+                (sample(x, z + 0.5) - sample(x, z - 0.5))
+            );
+            ControlPlane::on_slope(glm::vec3(x, sample(x, z), z), gradient)
+        })
+    }
+
+    /// Write the surface as a RON course file.
+    pub fn to_ron(&self) -> ron::ser::Result<String> {
+        ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+    }
+
+    /// Read a surface from a RON course file.
+    pub fn from_ron(ron: &str) -> ron::de::Result<Self> {
+        let cs: ControlSurface = ron::de::from_str(ron)?;
+        cs.validate().map_err(ron::de::Error::Message)?;
+        Ok(cs)
+    }
+
+    /// Check a deserialized surface has as many control planes as its size says.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.width < 2 || self.height < 2 || self.controls.len() != self.width * self.height {
+            Err(format!("Expected {} * {} control planes (at least 2 * 2), found {}", self.width, self.height, self.controls.len()))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Number of control planes along x (East)
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Number of control planes along z (North)
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn control(&self, i: usize, j: usize) -> &ControlPlane {
+        assert!(i < self.width && j < self.height);
+        &self.controls[i * self.height + j]
+    }
+
+    pub fn control_mut(&mut self, i: usize, j: usize) -> &mut ControlPlane {
+        assert!(i < self.width && j < self.height);
+        &mut self.controls[i * self.height + j]
+    }
+
+    /// The patch with control plane (i, j) as its south west corner.
+    pub fn patch(&self, i: usize, j: usize) -> BicubicPatch {
+        BicubicPatch::new(
+            self.control(i, j),
+            self.control(i, j + 1),
+            self.control(i + 1, j + 1),
+            self.control(i + 1, j)
+        )
     }
 
     pub fn rasterize(&self) -> Vec<PosNormTex> {
         let mut vec = Vec::new();
 
-        for i in 0..SIZE {
-            for j in 0..SIZE {
-                let mut control = self.controls[i][j].rasterize();
-                vec.append(&mut control);
-            }
+        for control in self.controls.iter() {
+            let mut control = control.rasterize();
+            vec.append(&mut control);
         };
         vec
     }
-}
\ No newline at end of file
+}
+
+// Also used by the terrain tests
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use ncollide3d::bounding_volume::HasBoundingVolume;
+
+    pub fn vec_bits(v: &glm::Vec3) -> [u32; 3] {
+        [v.x.to_bits(), v.y.to_bits(), v.z.to_bits()]
+    }
+
+    fn plane_bits(plane: &ControlPlane) -> Vec<u32> {
+        let q = plane.rotation.coords;
+        let mut bits = vec_bits(&plane.pos).to_vec();
+        bits.extend([q.x, q.y, q.z, q.w, plane.north, plane.east, plane.south, plane.west].iter().map(|n| n.to_bits()));
+        bits
+    }
+
+    // The collision mesh's vertices and bounding box, and where rays dropped onto it land
+    fn collision_bits(patch: &BicubicPatch) -> Vec<u32> {
+        let mut bits = Vec::new();
+        let grid = patch.rasterize_indexed_with(8, [8; 4], |p, _, _, _| p * 8.0);
+        for p in grid.vertices.iter() {
+            bits.extend_from_slice(&vec_bits(p));
+        };
+        bits.extend(mesh_bits(&patch.collision_mesh(8, 8.0)));
+        bits
+    }
+
+    /// A collision mesh's bounding box, and where a grid of rays dropped onto it land, as raw bits
+    pub fn mesh_bits(mesh: &TriMesh<f32>) -> Vec<u32> {
+        let mut bits = Vec::new();
+        let aabb = mesh.bounding_volume(&Isometry3::identity());
+        let (mins, maxs) = (aabb.mins().coords, aabb.maxs().coords);
+        bits.extend_from_slice(&vec_bits(&mins));
+        bits.extend_from_slice(&vec_bits(&maxs));
+
+        for a in 0..5 {
+            for b in 0..5 {
+                let x = mins.x + (maxs.x - mins.x) * a as f32 / 4.0;
+                let z = mins.z + (maxs.z - mins.z) * b as f32 / 4.0;
+                let ray = Ray::new(Point3::new(x, maxs.y + 1.0, z), glm::vec3(0.0, -1.0, 0.0));
+                match mesh.toi_and_normal_and_uv_with_ray(&Isometry3::identity(), &ray, false) {
+                    Some(hit) => {
+                        bits.push(hit.toi.to_bits());
+                        bits.extend_from_slice(&vec_bits(&hit.normal));
+                    },
+                    None => bits.push(u32::max_value())
+                }
+            }
+        };
+        bits
+    }
+
+    // Every control plane, patch control net and collision mesh of a surface, as raw bits
+    fn surface_bits(cs: &ControlSurface) -> Vec<u32> {
+        let mut bits: Vec<u32> = cs.controls.iter().flat_map(plane_bits).collect();
+        for i in 0..(cs.width() - 1) {
+            for j in 0..(cs.height() - 1) {
+                let patch = cs.patch(i, j);
+                for point in patch.control_net().iter().flat_map(|row| row.iter()) {
+                    bits.extend_from_slice(&vec_bits(point));
+                };
+                bits.extend(collision_bits(&patch));
+            }
+        };
+        bits
+    }
+
+    #[test]
+    fn same_seed_gives_identical_surfaces() {
+        let a = ControlSurface::from_seed(4, 3, 42);
+        let b = ControlSurface::from_seed(4, 3, 42);
+        assert_eq!(surface_bits(&a), surface_bits(&b));
+    }
+
+    #[test]
+    fn different_seeds_give_different_surfaces() {
+        let a = ControlSurface::from_seed(4, 3, 42);
+        let b = ControlSurface::from_seed(4, 3, 43);
+        assert!(a.controls.iter().zip(b.controls.iter()).any(|(a, b)| plane_bits(a) != plane_bits(b)));
+        assert_ne!(surface_bits(&a), surface_bits(&b));
+    }
+
+    #[test]
+    fn seeded_surface_round_trips_through_ron() {
+        let cs = ControlSurface::from_seed(5, 4, 9);
+        let loaded = ControlSurface::from_ron(&cs.to_ron().unwrap()).unwrap();
+
+        assert_eq!((loaded.width(), loaded.height()), (5, 4));
+        let planes = |cs: &ControlSurface| cs.controls.iter().map(plane_bits).collect::<Vec<_>>();
+        assert_eq!(planes(&loaded), planes(&cs));
+    }
+
+    #[test]
+    fn course_with_mismatched_size_is_rejected() {
+        // A plane missing
+        let mut cs = ControlSurface::from_seed(3, 3, 1);
+        cs.controls.pop();
+        assert!(ControlSurface::from_ron(&cs.to_ron().unwrap()).is_err());
+
+        // A width that doesn't match the planes
+        let mut cs = ControlSurface::from_seed(3, 3, 1);
+        cs.width = 4;
+        assert!(ControlSurface::from_ron(&cs.to_ron().unwrap()).is_err());
+
+        // The right number of planes, but too narrow to make a patch
+        let mut cs = ControlSurface::from_seed(3, 3, 1);
+        cs.width = 1;
+        cs.height = 9;
+        assert!(ControlSurface::from_ron(&cs.to_ron().unwrap()).is_err());
+    }
+}
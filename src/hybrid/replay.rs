@@ -0,0 +1,217 @@
+// Recording each player's input for every physics step, and replaying it without a window.
+// Physics steps are fixed and the terrain regenerates from its seed, so a replay puts the balls
+// exactly where they were when it was recorded.
+
+use glm;
+use ron;
+use ron::ser::PrettyConfig;
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use super::{surface_contact, Ball, Chunk, TickInput, CHUNK_SCALE};
+use super::physics::{BallParams, Contact};
+use super::proc_geom::{ControlSurface, Profile};
+use super::terrain::{ChunkGeometry, TerrainSource};
+
+/// Largest distance a replayed ball may be from where it was recorded, in world units
+pub const TOLERANCE: f32 = 1e-4;
+
+fn to_io_error<E: ToString>(err: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err.to_string())
+}
+
+/// The terrain a recording was made on. Only seeded terrain is recorded, as it regenerates exactly.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct RecordedTerrain {
+    pub seed: u64,
+    pub size: Option<(usize, usize)>,
+    pub profile: Profile
+}
+
+impl RecordedTerrain {
+    pub fn source(&self) -> TerrainSource {
+        match self.size {
+            Some((width, height)) => TerrainSource::Surface(ControlSurface::from_profile(width, height, self.seed, &self.profile)),
+            None => TerrainSource::Procedural { seed: self.seed, profile: self.profile, edits: HashMap::new() }
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct PlayerRecording {
+    /// The player's number, from PlayerInput
+    pub player: usize,
+    /// Where the player's ball started
+    pub spawn: [f32; 3],
+    /// The physics step the player joined on
+    pub start: u64,
+    pub inputs: Vec<TickInput>,
+    /// Where the ball was after each step, for checking replays against
+    pub positions: Vec<[f32; 3]>
+}
+
+/// Every player's input for every physics step of a game.
+/// Terrain edits made in the editor aren't recorded, so recordings with edits won't replay.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Recording {
+    pub terrain: RecordedTerrain,
+    /// In the order the players were first recorded
+    pub players: Vec<PlayerRecording>
+}
+
+impl Recording {
+    pub fn new(terrain: RecordedTerrain) -> Self {
+        Recording {
+            terrain,
+            players: Vec::new()
+        }
+    }
+
+    /// Records a step for one player, who is added on their first step.
+    /// Each player's steps must be recorded in order, without gaps.
+    pub fn record(&mut self, tick: u64, player: usize, spawn: &glm::Vec3, input: &TickInput, position: &glm::Vec3) {
+        let index = match self.players.iter().position(|recording| recording.player == player) {
+            Some(index) => index,
+            None => {
+                self.players.push(PlayerRecording {
+                    player,
+                    spawn: [spawn.x, spawn.y, spawn.z],
+                    start: tick,
+                    inputs: Vec::new(),
+                    positions: Vec::new()
+                });
+                self.players.len() - 1
+            }
+        };
+        let recording = &mut self.players[index];
+        recording.inputs.push(*input);
+        recording.positions.push([position.x, position.y, position.z]);
+    }
+
+    /// The trajectories of the balls while this was recorded.
+    pub fn trajectory(&self) -> Trajectory {
+        Trajectory {
+            players: self.players.iter().map(|player| player.positions.clone()).collect()
+        }
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let ron = ron::ser::to_string_pretty(self, PrettyConfig::default()).map_err(to_io_error)?;
+        fs::write(path, ron)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        ron::de::from_str(&fs::read_to_string(path)?).map_err(to_io_error)
+    }
+}
+
+/// Resource the BallSystem records into, when the game is recording
+#[derive(Default)]
+pub struct Recorder {
+    pub recording: Option<Recording>,
+    /// Physics steps since the game started
+    pub tick: u64
+}
+
+/// Where each player's ball was after each of their physics steps, in the order of Recording::players
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Trajectory {
+    pub players: Vec<Vec<[f32; 3]>>
+}
+
+impl Trajectory {
+    /// Describes the first step at which the trajectories are further apart than the tolerance.
+    pub fn compare(&self, other: &Trajectory, tolerance: f32) -> Result<(), String> {
+        if self.players.len() != other.players.len() {
+            return Err(format!("{} players, expected {}", self.players.len(), other.players.len()))
+        };
+        for (player, (positions, expected)) in self.players.iter().zip(other.players.iter()).enumerate() {
+            if positions.len() != expected.len() {
+                return Err(format!("Player {} has {} steps, expected {}", player + 1, positions.len(), expected.len()))
+            };
+            for (step, (position, expected)) in positions.iter().zip(expected.iter()).enumerate() {
+                let distance = glm::distance(&glm::make_vec3(position), &glm::make_vec3(expected));
+                if !(distance <= tolerance) {
+                    return Err(format!("Player {} is {} world units off at step {}, at {:?} instead of {:?}",
+                                       player + 1, distance, step, position, expected))
+                }
+            }
+        };
+        Ok(())
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let ron = ron::ser::to_string_pretty(self, PrettyConfig::default()).map_err(to_io_error)?;
+        fs::write(path, ron)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        ron::de::from_str(&fs::read_to_string(path)?).map_err(to_io_error)
+    }
+}
+
+/// The terrain's chunks, generated as balls reach them, for stepping balls without the ECS.
+pub struct HeadlessCourse {
+    source: TerrainSource,
+    // None where there's no terrain
+    chunks: HashMap<(i32, i32), Option<Chunk>>
+}
+
+impl HeadlessCourse {
+    pub fn new(source: TerrainSource) -> Self {
+        HeadlessCourse {
+            source,
+            chunks: HashMap::new()
+        }
+    }
+
+    /// The surface under a position, found the same way as in game.
+    pub fn contact(&mut self, position: &glm::Vec3) -> Option<Contact> {
+        let (i, j) = ((position.x / CHUNK_SCALE).floor() as i32, (position.z / CHUNK_SCALE).floor() as i32);
+        // A chunk's bounding box only reaches a little way into the cells around it
+        let coords: Vec<(i32, i32)> = (-1..2).flat_map(|di| (-1..2).map(move |dj| (i + di, j + dj))).collect();
+
+        for coord in coords.iter() {
+            if !self.chunks.contains_key(coord) {
+                let chunk = self.source.patch(coord.0, coord.1).map(|patch| ChunkGeometry::new(*coord, patch, &[]).chunk);
+                self.chunks.insert(*coord, chunk);
+            }
+        };
+
+        let chunks = &self.chunks;
+        surface_contact(coords.iter().filter_map(|coord| chunks[coord].as_ref()), position)
+    }
+}
+
+/// Steps every player's ball through their recorded input, returning where the balls went.
+pub fn replay(recording: &Recording) -> Trajectory {
+    let mut course = HeadlessCourse::new(recording.terrain.source());
+    let params = BallParams::default();
+
+    let mut balls: Vec<Ball> = recording.players.iter()
+        .map(|player| Ball::new(glm::make_vec3(&player.spawn)))
+        .collect();
+    let mut trajectory = Trajectory {
+        players: recording.players.iter().map(|player| Vec::with_capacity(player.inputs.len())).collect()
+    };
+
+    let end = recording.players.iter()
+        .map(|player| player.start + player.inputs.len() as u64)
+        .max()
+        .unwrap_or(0);
+    for tick in 0..end {
+        for (player, (recorded, ball)) in recording.players.iter().zip(balls.iter_mut()).enumerate() {
+            if tick < recorded.start || tick >= recorded.start + recorded.inputs.len() as u64 {
+                continue
+            };
+            let input = &recorded.inputs[(tick - recorded.start) as usize];
+            ball.tick(input, &params, |position| course.contact(position));
+            let position = ball.current.position;
+            trajectory.players[player].push([position.x, position.y, position.z]);
+        }
+    };
+    trajectory
+}
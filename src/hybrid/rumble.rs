@@ -0,0 +1,126 @@
+// Impacts between balls and the terrain, and the rumble each one should give the player's pad.
+// Deciding on the effect is kept apart from playing it, so it can be checked without a pad.
+
+use amethyst::ecs::prelude::Entity;
+use glm;
+
+// Hitting the ground faster than this after being in the air rumbles, in world units per second
+const LANDING_SPEED: f32 = 3.0;
+// While rolling, only hitting a slope steeper than this and faster than SLOPE_SPEED rumbles
+const STEEP_SLOPE_DEGREES: f32 = 35.0;
+const SLOPE_SPEED: f32 = 4.0;
+// Impact speed at which an effect reaches full strength
+const FULL_SPEED: f32 = 20.0;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ImpactKind {
+    /// Coming down onto the surface from the air
+    Landing,
+    /// Running into a steep slope while already on the ground
+    Slope
+}
+
+/// Sent by the BallSystem when a ball hits the terrain hard
+#[derive(Clone, Copy, Debug)]
+pub struct ImpactEvent {
+    pub ball: Entity,
+    pub kind: ImpactKind,
+    /// Speed into the surface, in world units per second
+    pub speed: f32
+}
+
+/// A rumble to play on a pad, motor strengths from 0 to 1
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct RumbleEffect {
+    /// The low frequency motor, for heavy thuds
+    pub strong: f32,
+    /// The high frequency motor, for sharp knocks
+    pub weak: f32,
+    pub duration_ms: u32
+}
+
+/// Whether hitting the surface with the given speed and normal counts as an impact.
+pub fn classify_impact(speed: f32, normal: &glm::Vec3, airborne: bool) -> Option<ImpactKind> {
+    let steep = normal.y < STEEP_SLOPE_DEGREES.to_radians().cos();
+    if airborne && speed > LANDING_SPEED {
+        Some(ImpactKind::Landing)
+    } else if !airborne && steep && speed > SLOPE_SPEED {
+        Some(ImpactKind::Slope)
+    } else {
+        None
+    }
+}
+
+/// The rumble for an impact, stronger and longer the faster it was.
+pub fn effect_for_impact(kind: ImpactKind, speed: f32) -> RumbleEffect {
+    let intensity = (speed / FULL_SPEED).max(0.0).min(1.0);
+    match kind {
+        ImpactKind::Landing => RumbleEffect {
+            strong: intensity,
+            weak: intensity * 0.5,
+            duration_ms: 80 + (intensity * 220.0) as u32
+        },
+        ImpactKind::Slope => RumbleEffect {
+            strong: intensity * 0.3,
+            weak: intensity,
+            duration_ms: 60 + (intensity * 90.0) as u32
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn up() -> glm::Vec3 {
+        glm::vec3(0.0, 1.0, 0.0)
+    }
+
+    // 45 degrees, steeper than STEEP_SLOPE_DEGREES
+    fn steep() -> glm::Vec3 {
+        glm::vec3(1.0, 1.0, 0.0).normalize()
+    }
+
+    #[test]
+    fn soft_landings_dont_rumble() {
+        assert_eq!(classify_impact(LANDING_SPEED - 0.1, &up(), true), None);
+        assert_eq!(classify_impact(LANDING_SPEED, &up(), true), None);
+        assert_eq!(classify_impact(LANDING_SPEED + 0.1, &up(), true), Some(ImpactKind::Landing));
+    }
+
+    #[test]
+    fn rolling_only_rumbles_on_steep_slopes() {
+        // Fast, but on flat ground
+        assert_eq!(classify_impact(SLOPE_SPEED * 2.0, &up(), false), None);
+        // Steep, but slow
+        assert_eq!(classify_impact(SLOPE_SPEED - 0.1, &steep(), false), None);
+        assert_eq!(classify_impact(SLOPE_SPEED + 0.1, &steep(), false), Some(ImpactKind::Slope));
+        // Coming down onto a steep slope from the air is a landing
+        assert_eq!(classify_impact(SLOPE_SPEED + 0.1, &steep(), true), Some(ImpactKind::Landing));
+    }
+
+    #[test]
+    fn intensity_is_capped_at_full_speed() {
+        for kind in [ImpactKind::Landing, ImpactKind::Slope].iter() {
+            let full = effect_for_impact(*kind, FULL_SPEED);
+            assert_eq!(effect_for_impact(*kind, FULL_SPEED * 3.0), full);
+            assert!(full.strong <= 1.0 && full.weak <= 1.0);
+        };
+        let landing = effect_for_impact(ImpactKind::Landing, FULL_SPEED);
+        assert_eq!(landing, RumbleEffect { strong: 1.0, weak: 0.5, duration_ms: 300 });
+    }
+
+    #[test]
+    fn harder_impacts_rumble_harder_and_longer() {
+        for kind in [ImpactKind::Landing, ImpactKind::Slope].iter() {
+            let effects: Vec<RumbleEffect> = [4.0, 8.0, 12.0, 16.0].iter()
+                .map(|speed| effect_for_impact(*kind, *speed))
+                .collect();
+            for pair in effects.windows(2) {
+                assert!(pair[1].strong > pair[0].strong, "{:?}: {:?}", kind, pair);
+                assert!(pair[1].weak > pair[0].weak, "{:?}: {:?}", kind, pair);
+                assert!(pair[1].duration_ms > pair[0].duration_ms, "{:?}: {:?}", kind, pair);
+            }
+        }
+    }
+}
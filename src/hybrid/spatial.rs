@@ -0,0 +1,106 @@
+use amethyst::{
+    ecs::prelude::*,
+    core::nalgebra::Point3
+};
+use ncollide3d::bounding_volume::AABB;
+
+use std::collections::HashMap;
+
+/// A uniform grid over the xz plane, mapping each cell to the chunks whose bounding box overlaps it.
+pub struct ChunkIndex {
+    cell_size: f32,
+    cells: HashMap<(i32, i32), Vec<Entity>>,
+    // Keyed by entity id, as removed entities may no longer be alive to compare generations
+    boxes: HashMap<u32, AABB<f32>>
+}
+
+impl Default for ChunkIndex {
+    fn default() -> Self {
+        ChunkIndex::new(::hybrid::CHUNK_SCALE)
+    }
+}
+
+impl ChunkIndex {
+    pub fn new(cell_size: f32) -> Self {
+        ChunkIndex {
+            cell_size,
+            cells: HashMap::new(),
+            boxes: HashMap::new()
+        }
+    }
+
+    fn cell(&self, x: f32, z: f32) -> (i32, i32) {
+        ((x / self.cell_size).floor() as i32, (z / self.cell_size).floor() as i32)
+    }
+
+    // All cells overlapped by the box in the xz plane
+    fn cells_of(&self, bounding_box: &AABB<f32>) -> Vec<(i32, i32)> {
+        let (x0, z0) = self.cell(bounding_box.mins().x, bounding_box.mins().z);
+        let (x1, z1) = self.cell(bounding_box.maxs().x, bounding_box.maxs().z);
+
+        let mut cells = Vec::new();
+        for x in x0..(x1 + 1) {
+            for z in z0..(z1 + 1) {
+                cells.push((x, z))
+            }
+        };
+        cells
+    }
+
+    pub fn insert(&mut self, entity: Entity, bounding_box: AABB<f32>) {
+        self.remove(entity);
+
+        for cell in self.cells_of(&bounding_box) {
+            self.cells.entry(cell).or_insert_with(Vec::new).push(entity);
+        };
+        self.boxes.insert(entity.id(), bounding_box);
+    }
+
+    pub fn remove(&mut self, entity: Entity) {
+        if let Some(bounding_box) = self.boxes.remove(&entity.id()) {
+            for cell in self.cells_of(&bounding_box) {
+                let empty = match self.cells.get_mut(&cell) {
+                    Some(entities) => {
+                        entities.retain(|e| e.id() != entity.id());
+                        entities.is_empty()
+                    },
+                    None => false
+                };
+                if empty {
+                    self.cells.remove(&cell);
+                }
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.boxes.len()
+    }
+
+    /// Chunks whose bounding box lies directly above or below the point, i.e. may be hit by a vertical ray through it.
+    pub fn column<'a>(&'a self, point: &Point3<f32>) -> impl Iterator<Item = Entity> + 'a {
+        let (x, z) = (point.x, point.z);
+        let boxes = &self.boxes;
+
+        self.cells.get(&self.cell(x, z))
+            .into_iter()
+            .flat_map(|entities| entities.iter())
+            .filter(move |entity| {
+                let bounding_box = &boxes[&entity.id()];
+                bounding_box.mins().x <= x && x <= bounding_box.maxs().x &&
+                bounding_box.mins().z <= z && z <= bounding_box.maxs().z
+            })
+            .cloned()
+    }
+
+    /// Chunks whose bounding box contains the point.
+    pub fn containing<'a>(&'a self, point: &'a Point3<f32>) -> impl Iterator<Item = Entity> + 'a {
+        let boxes = &self.boxes;
+
+        self.column(point)
+            .filter(move |entity| {
+                let bounding_box = &boxes[&entity.id()];
+                bounding_box.mins().y <= point.y && point.y <= bounding_box.maxs().y
+            })
+    }
+}
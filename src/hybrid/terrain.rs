@@ -0,0 +1,340 @@
+use amethyst::{
+    prelude::*,
+    ecs::prelude::*,
+    assets::{Asset, AssetStorage, Directory, Handle, Loader, ProgressCounter, RonFormat},
+    core::nalgebra::Isometry3,
+    renderer::{Material, PosNormTex, TriplanarMaterial},
+    utils::application_root_dir
+};
+use glm;
+use image::{self, ImageError, ImageResult};
+use ncollide3d::bounding_volume::{AABB, HasBoundingVolume, BoundingVolume};
+use rand::{thread_rng, Rng};
+use rayon::prelude::*;
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use super::{Chunk, Lod, CHUNK_SCALE};
+use super::proc_geom::{BicubicPatch, ControlPlane, ControlSurface, Profile};
+
+const COLLISION_RES: i32 = 8;
+
+// Patch resolution to use up to a given distance from the camera. Resolutions must be powers of two,
+// so that a coarser neighbour's edge vertices always line up with ours.
+const LOD_LEVELS: [(f32, i32); 4] = [
+    (48.0, 16),
+    (112.0, 8),
+    (240.0, 4),
+    (::std::f32::MAX, 2)
+];
+
+/// Patch resolution for a chunk this far from the nearest camera.
+pub fn lod_resolution(distance: f32) -> i32 {
+    LOD_LEVELS.iter()
+        .find(|(max_distance, _)| distance <= *max_distance)
+        .map(|(_, res)| *res)
+        .unwrap_or(2)
+}
+
+/// Distance from a point to the nearest camera, f32::MAX without any.
+pub fn eye_distance(point: &glm::Vec3, eyes: &[glm::Vec3]) -> f32 {
+    eyes.iter().map(|eye| glm::distance(point, eye)).fold(::std::f32::MAX, f32::min)
+}
+
+/// How to create the terrain when the game starts.
+pub enum TerrainConfig {
+    /// Generated from a seed, endless unless a width and height are given
+    Seeded { seed: u64, size: Option<(usize, usize)>, profile: Profile },
+    /// Traced from a grayscale image, loaded with load_heightmap before the game starts
+    /// so a bad image is reported before the window opens
+    Heightmap(ControlSurface),
+    /// A RON course file in the resources directory
+    Course { path: String }
+}
+
+impl TerrainConfig {
+    pub fn source(&self, world: &mut World) -> TerrainSource {
+        let app_root = application_root_dir();
+
+        match *self {
+            TerrainConfig::Seeded { seed, size: Some((width, height)), ref profile } =>
+                TerrainSource::Surface(ControlSurface::from_profile(width, height, seed, profile)),
+            TerrainConfig::Seeded { seed, size: None, profile } =>
+                TerrainSource::Procedural { seed, profile, edits: HashMap::new() },
+            TerrainConfig::Heightmap(ref cs) => TerrainSource::Surface(cs.clone()),
+            TerrainConfig::Course { ref path } => {
+                let mut loader = world.write_resource::<Loader>();
+                loader.add_source(RESOURCES, Directory::new(format!("{}/resources", app_root)));
+
+                let mut progress = ProgressCounter::new();
+                let handle = loader.load_from(path.as_str(), RonFormat, (), RESOURCES, &mut progress, &world.read_resource());
+                TerrainSource::Loading { path: path.clone(), handle, progress }
+            }
+        }
+    }
+}
+
+// Loader source for course files
+const RESOURCES: &str = "resources";
+
+impl Asset for ControlSurface {
+    const NAME: &'static str = "hybrid::ControlSurface";
+    type Data = Self;
+    type HandleStorage = VecStorage<Handle<Self>>;
+}
+
+/// Save a course to a RON file in the resources directory.
+pub fn save_course(cs: &ControlSurface, path: &str) -> io::Result<()> {
+    let ron = cs.to_ron().map_err(|err| io::Error::new(io::ErrorKind::Other, err.to_string()))?;
+    fs::write(format!("{}/resources/{}", application_root_dir(), path), ron)
+}
+
+/// Height of white in a heightmap, unless another is given
+pub const HEIGHT_SCALE: f32 = 16.0;
+
+/// Load a heightmap image as a control surface, by default with one control plane per pixel.
+/// The image and the surface must both be at least 2x2.
+pub fn load_heightmap<P: AsRef<Path>>(path: P, size: Option<(usize, usize)>, height_scale: f32) -> ImageResult<ControlSurface> {
+    let image = image::open(path)?.to_luma();
+    let (width, height) = size.unwrap_or((image.width() as usize, image.height() as usize));
+    if image.width() < 2 || image.height() < 2 || width < 2 || height < 2 {
+        return Err(ImageError::DimensionError)
+    };
+    Ok(ControlSurface::from_heightmap(&image, width, height, height_scale))
+}
+
+/// Where the control planes of the terrain come from.
+pub enum TerrainSource {
+    /// A bounded course
+    Surface(ControlSurface),
+    /// An endless terrain, derived from the seed and the position of each control plane,
+    /// apart from those changed in the editor
+    Procedural { seed: u64, profile: Profile, edits: HashMap<(i32, i32), ControlPlane> },
+    /// A course still being loaded by the asset loader, see resolve
+    Loading { path: String, handle: Handle<ControlSurface>, progress: ProgressCounter }
+}
+
+impl TerrainSource {
+    pub fn plane(&self, i: i32, j: i32) -> Option<ControlPlane> {
+        match *self {
+            TerrainSource::Surface(ref cs) => {
+                if i >= 0 && j >= 0 && (i as usize) < cs.width() && (j as usize) < cs.height() {
+                    Some(*cs.control(i as usize, j as usize))
+                } else {
+                    None
+                }
+            },
+            TerrainSource::Procedural { seed, ref profile, ref edits } =>
+                Some(edits.get(&(i, j)).cloned().unwrap_or_else(|| profile.plane(seed, i, j))),
+            TerrainSource::Loading { .. } => None
+        }
+    }
+
+    /// Replace the control plane at (i, j). Returns false if the terrain doesn't extend that far.
+    pub fn set_plane(&mut self, i: i32, j: i32, plane: ControlPlane) -> bool {
+        match *self {
+            TerrainSource::Surface(ref mut cs) => {
+                if i >= 0 && j >= 0 && (i as usize) < cs.width() && (j as usize) < cs.height() {
+                    *cs.control_mut(i as usize, j as usize) = plane;
+                    true
+                } else {
+                    false
+                }
+            },
+            TerrainSource::Procedural { ref mut edits, .. } => {
+                edits.insert((i, j), plane);
+                true
+            },
+            TerrainSource::Loading { .. } => false
+        }
+    }
+
+    /// Swap a loaded course in for its handle. A course that fails to load is reported, and replaced
+    /// with endless seeded terrain so there is still something to ride on.
+    pub fn resolve(&mut self, surfaces: &AssetStorage<ControlSurface>) {
+        let (path, loaded) = match *self {
+            TerrainSource::Loading { ref path, ref handle, ref progress } => {
+                let loaded = if progress.num_failed() > 0 {
+                    Err("the file couldn't be read, see the log".to_string())
+                } else {
+                    match surfaces.get(handle) {
+                        Some(cs) => cs.validate().map(|()| cs.clone()),
+                        None => return
+                    }
+                };
+                (path.clone(), loaded)
+            },
+            _ => return
+        };
+
+        match loaded {
+            Ok(cs) => *self = TerrainSource::Surface(cs),
+            Err(err) => {
+                let seed = thread_rng().gen();
+                println!("Failed to load course {}, using seed {} instead: {}", path, seed, err);
+                *self = TerrainSource::Procedural { seed, profile: Profile::Bumpy, edits: HashMap::new() };
+            }
+        }
+    }
+
+    /// Whether there is a patch with control plane (i, j) as its south west corner.
+    pub fn contains(&self, i: i32, j: i32) -> bool {
+        match *self {
+            TerrainSource::Surface(ref cs) =>
+                i >= 0 && j >= 0 && (i as usize) < cs.width() - 1 && (j as usize) < cs.height() - 1,
+            TerrainSource::Procedural { .. } => true,
+            TerrainSource::Loading { .. } => false
+        }
+    }
+
+    /// The patch with control plane (i, j) as its south west corner, if the terrain extends that far.
+    pub fn patch(&self, i: i32, j: i32) -> Option<BicubicPatch> {
+        let sw = self.plane(i, j)?;
+        let nw = self.plane(i, j + 1)?;
+        let ne = self.plane(i + 1, j + 1)?;
+        let se = self.plane(i + 1, j)?;
+        Some(BicubicPatch::new(&sw, &nw, &ne, &se))
+    }
+}
+
+/// The terrain being played on, chunks are streamed in around the balls by the TerrainStreamSystem.
+pub struct Terrain {
+    pub source: TerrainSource,
+    /// Chunks within this many chunks of a ball are kept loaded
+    pub view_distance: i32,
+    /// Chunks whose control planes changed, and need to be regenerated
+    pub dirty: Vec<(i32, i32)>
+}
+
+impl Terrain {
+    pub fn new(source: TerrainSource, view_distance: i32) -> Self {
+        Terrain {
+            source,
+            view_distance,
+            dirty: Vec::new()
+        }
+    }
+
+    /// Replace the control plane at (i, j), and mark the four chunks it shapes for regeneration.
+    pub fn edit(&mut self, i: i32, j: i32, plane: ControlPlane) {
+        if self.source.set_plane(i, j, plane) {
+            self.dirty.extend_from_slice(&[(i - 1, j - 1), (i - 1, j), (i, j - 1), (i, j)]);
+        }
+    }
+}
+
+// Albedo textures for the surfaces facing along each axis, also referenced by exported meshes
+pub const ALBEDO_XY: &str = "texture/Rock08_col.jpg";
+pub const ALBEDO_YZ: &str = "texture/Rock08_col.jpg";
+pub const ALBEDO_XZ: &str = "texture/Ice04_col.jpg";
+
+/// The materials shared by every chunk.
+pub struct TerrainMaterials {
+    pub mtl_xy: Material,
+    pub mtl_yz: Material,
+    pub mtl_xz: Material
+}
+
+impl TerrainMaterials {
+    pub fn triplanar(&self) -> TriplanarMaterial {
+        TriplanarMaterial {
+            mtl_xy: self.mtl_xy.clone(),
+            mtl_yz: self.mtl_yz.clone(),
+            mtl_xz: self.mtl_xz.clone()
+        }
+    }
+}
+
+/// Everything needed to spawn a chunk entity, built without access to the world.
+pub struct ChunkGeometry {
+    pub chunk: Chunk,
+    pub vertices: Vec<PosNormTex>,
+    /// The tessellation of the vertices
+    pub lod: Lod
+}
+
+impl ChunkGeometry {
+    /// The render mesh is tessellated for the nearest of the cameras at eyes, as the LodSystem would.
+    pub fn new(coord: (i32, i32), patch: BicubicPatch, eyes: &[glm::Vec3]) -> Self {
+        let collision_mesh = patch.collision_mesh(COLLISION_RES, CHUNK_SCALE);
+
+        let mut bounding_box: AABB<f32> = collision_mesh.bounding_volume(&Isometry3::identity());
+        bounding_box.loosen(3.0);
+
+        // The neighbours may not be generated yet, so they are taken to be level with this chunk.
+        // If they aren't, the LodSystem restitches the edges once it sees them.
+        let center = bounding_box.center().coords;
+        let res = lod_resolution(eye_distance(&center, eyes));
+        let mut edges = [res; 4];
+        for (edge, (di, dj)) in edges.iter_mut().zip([(0.0, 1.0), (1.0, 0.0), (0.0, -1.0), (-1.0, 0.0)].iter()) {
+            let neighbour = center + glm::vec3(*di, 0.0, *dj) * CHUNK_SCALE;
+            *edge = res.min(lod_resolution(eye_distance(&neighbour, eyes)));
+        };
+        let vertices = patch.rasterize_stitched(res, edges);
+
+        ChunkGeometry {
+            chunk: Chunk {
+                coord,
+                collision_mesh,
+                patch,
+                bounding_box
+            },
+            vertices,
+            lod: Lod { res, edges }
+        }
+    }
+}
+
+/// Build the geometry of the chunks at the given coordinates, spread across worker threads.
+/// Coordinates outside of the terrain are skipped.
+pub fn generate_chunks(source: &TerrainSource, coords: &[(i32, i32)], eyes: &[glm::Vec3]) -> Vec<ChunkGeometry> {
+    coords.par_iter()
+        .filter_map(|&coord| source.patch(coord.0, coord.1).map(|patch| ChunkGeometry::new(coord, patch, eyes)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::proc_geom::tests::{mesh_bits, vec_bits};
+
+    // The render vertices, bounding box and collision mesh of a chunk as raw bits
+    fn geometry_bits(geometry: &ChunkGeometry) -> Vec<u32> {
+        let mut bits = vec![geometry.lod.res as u32];
+        bits.extend(geometry.lod.edges.iter().map(|&edge| edge as u32));
+        for vertex in geometry.vertices.iter() {
+            bits.extend_from_slice(&vec_bits(&vertex.position));
+            bits.extend_from_slice(&vec_bits(&vertex.normal));
+            bits.extend_from_slice(&[vertex.tex_coord.x.to_bits(), vertex.tex_coord.y.to_bits()]);
+        };
+
+        let chunk = &geometry.chunk;
+        let (mins, maxs) = (chunk.bounding_box.mins().coords, chunk.bounding_box.maxs().coords);
+        bits.extend_from_slice(&vec_bits(&mins));
+        bits.extend_from_slice(&vec_bits(&maxs));
+        bits.extend(mesh_bits(&chunk.collision_mesh));
+        bits
+    }
+
+    #[test]
+    fn parallel_and_serial_generation_match() {
+        let source = TerrainSource::Procedural { seed: 7, profile: Profile::Bumpy, edits: HashMap::new() };
+        let coords: Vec<(i32, i32)> = (-3..4).flat_map(|i| (-2..3).map(move |j| (i, j))).collect();
+        // Far enough to the West that the chunks aren't all tessellated alike
+        let eyes = [glm::vec3(-60.0, 10.0, 0.0)];
+
+        let parallel = generate_chunks(&source, &coords, &eyes);
+        let serial: Vec<ChunkGeometry> = coords.iter()
+            .map(|&coord| ChunkGeometry::new(coord, source.patch(coord.0, coord.1).unwrap(), &eyes))
+            .collect();
+
+        assert_eq!(parallel.len(), serial.len());
+        for (parallel, serial) in parallel.iter().zip(serial.iter()) {
+            assert_eq!(parallel.chunk.coord, serial.chunk.coord);
+            assert_eq!(geometry_bits(parallel), geometry_bits(serial), "Chunk {:?} differs", serial.chunk.coord);
+        }
+    }
+}
@@ -0,0 +1,15 @@
+extern crate amethyst;
+extern crate gilrs;
+extern crate image;
+extern crate rand;
+extern crate ron;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate nalgebra;
+extern crate nalgebra_glm as glm;
+extern crate ncollide3d;
+extern crate rayon;
+
+pub mod hybrid;
+pub mod systems;
@@ -1,80 +1,107 @@
 extern crate amethyst;
 extern crate gilrs;
 extern crate rand;
-extern crate nalgebra;
-extern crate nalgebra_glm as glm;
-extern crate ncollide3d;
+extern crate hybrid as game;
 
-mod hybrid;
-mod systems;
+use game::{hybrid, systems};
 
 use amethyst::{
     core::transform::TransformBundle,
     prelude::*,
     ecs::prelude::*,
-    ecs::shred::ResourceId,
     core::EventReader,
-    assets::PrefabLoaderSystem,
+    input::InputBundle,
+    assets::{PrefabLoaderSystem, Processor},
     renderer::{DisplayConfig, DrawShaded, DrawSkybox, DrawTriplanar, DrawDebugLines, PosColorNorm, PosNormTex, Pipeline, RenderBundle, Stage},
     utils::{application_root_dir, scene::BasicScenePrefab},
 };
 
-use std::ops::Deref;
-use std::sync::{Arc, Mutex};
+use rand::{thread_rng, Rng};
 
-// Controller setup.
-// I'm not really sure how you're supposed to do Controller input in amethyst, so this is probably a massive hack.
-use gilrs::{Gilrs, Event};
+use std::env;
+use std::path::Path;
+
+// Gamepad events reach the states through this reader, which polls the Gamepads resource
+use gilrs::Event;
+use hybrid::gamepad::Gamepads;
 
 #[derive(Default)]
 struct PadEventReader;
 
-struct Pad(Arc<Mutex<Gilrs>>);
-
-impl<'a> SystemData<'a> for Pad {
-    fn setup(res: &mut Resources) {
-        let gilrs = Gilrs::new().unwrap();
-        res.insert(Pad(Arc::new(Mutex::new(gilrs))));
-    }
-
-    fn fetch(res: &'a Resources) -> Self {
-        let r = res.fetch::<Pad>();
-        let Pad(gilrs) = r.deref();
-        Pad(gilrs.clone())
-    }
-
-    fn reads() -> Vec<ResourceId> {
-        Vec::new()
-    }
-
-    fn writes() -> Vec<ResourceId> {
-        Vec::new()
-    }
-}
-
 impl<'a> EventReader<'a> for PadEventReader {
-    type SystemData = Pad;
+    type SystemData = Write<'a, Gamepads>;
     type Event = Event;
 
-    fn read(&mut self, Pad(gilrs_mutex): Pad, vec: &mut Vec<Event>) {
-        if let Ok(ref mut gilrs) = gilrs_mutex.try_lock() {
-            while let Some(event) = gilrs.next_event() {
-                vec.push(event)
-            }
-        } else {
-            panic!("Failed to aquire controller lock")
-        }
+    fn read(&mut self, mut gamepads: Self::SystemData, vec: &mut Vec<Event>) {
+        gamepads.poll(vec)
     }
 }
 
+// Bad command line arguments are reported through amethyst's error, before the window opens
+fn startup_error(message: String) -> amethyst::Error {
+    amethyst::core::Error::from(message).into()
+}
+
 fn main() -> amethyst::Result<()> {
     amethyst::start_logger(Default::default());
 
-    use hybrid::Hybrid;
+    use hybrid::{ControlSurface, Downhill, Hybrid, Profile, TerrainConfig};
+    use hybrid::controls::ActionMap;
+    use hybrid::terrain::{load_heightmap, HEIGHT_SCALE};
 
     let app_root = application_root_dir();
 
+    // Usage: hybrid [--downhill] [--heightmap=<image in assets>] [--height-scale=<height of white>]
+    //               [--course=<file in resources>] [--save-course=<file in resources>] [--export=<path>]
+    //               [--record=<path>] [seed] [width] [height]
+    // The terrain seed can be given to reproduce a course, and is endless unless a size is given
+    let (flags, args): (Vec<String>, Vec<String>) = env::args().partition(|arg| arg.starts_with("--"));
+    let seed = args.get(1)
+        .and_then(|arg| arg.parse().ok())
+        .unwrap_or_else(|| thread_rng().gen());
+    let size = args.get(2)
+        .and_then(|arg| arg.parse().ok())
+        .map(|width| {
+            let height = args.get(3)
+                .and_then(|arg| arg.parse().ok())
+                .unwrap_or(width);
+            (width, height)
+        });
+    let profile = if flags.iter().any(|flag| flag == "--downhill") {
+        Profile::Downhill(Downhill::default())
+    } else {
+        Profile::Bumpy
+    };
+    let flag_value = |name: &str| flags.iter()
+        .find(|flag| flag.starts_with(name) && flag[name.len()..].starts_with("="))
+        .map(|flag| flag[(name.len() + 1)..].to_string());
+
+    let assets_dir = format!("{}/assets/", app_root);
+
+    let height_scale = match flag_value("--height-scale") {
+        Some(scale) => scale.parse()
+            .map_err(|_| startup_error(format!("Invalid height scale: {}", scale)))?,
+        None => HEIGHT_SCALE
+    };
+    let terrain = match (flag_value("--heightmap"), flag_value("--course")) {
+        (Some(path), _) => {
+            let path = Path::new(&assets_dir).join(path);
+            let cs = load_heightmap(&path, size, height_scale)
+                .map_err(|err| startup_error(format!("Failed to load heightmap {}: {}", path.display(), err)))?;
+            TerrainConfig::Heightmap(cs)
+        },
+        (None, Some(path)) => TerrainConfig::Course { path },
+        (None, None) => {
+            println!("Terrain seed: {}", seed);
+            TerrainConfig::Seeded { seed, size, profile }
+        }
+    };
+    let save_course = flag_value("--save-course");
+    let export = flag_value("--export");
+    let record = flag_value("--record");
+
     let config = DisplayConfig::load(format!("{}/resources/display_config.ron", app_root));
+    let actions = ActionMap::load(&format!("{}/resources/controls.ron", app_root));
 
     let pipe = Pipeline::build().with_stage(
         Stage::with_backbuffer()
@@ -85,15 +112,21 @@ fn main() -> amethyst::Result<()> {
             .with_pass(DrawDebugLines::<PosColorNorm>::new())
     );
 
-    let assets_dir = format!("{}/assets/", app_root);
-
     let game_data = GameDataBuilder::default()
         .with(PrefabLoaderSystem::<BasicScenePrefab<Vec<PosNormTex>>>::default(), "", &[])
+        .with(Processor::<ControlSurface>::new(), "course_processor", &[])
         .with_bundle(RenderBundle::new(pipe, Some(config)))?
         .with_bundle(TransformBundle::new())?
-        .with(systems::BallSystem::new(), "ball_system", &[])
-        .with(systems::FollowSystem::new(), "follow_system", &[]);
-    let mut game = CoreApplication::<_, gilrs::Event, PadEventReader>::new(assets_dir, Hybrid, game_data)?;
+        .with_bundle(InputBundle::<String, String>::new())?
+        .with(systems::PlayerInputSystem::new(actions), "player_input_system", &["input_system"])
+        .with(systems::TerrainStreamSystem::new(), "terrain_stream_system", &[])
+        .with(systems::ChunkIndexSystem::new(), "chunk_index_system", &["terrain_stream_system"])
+        .with(systems::BallSystem::new(), "ball_system", &["chunk_index_system", "player_input_system"])
+        .with(systems::RumbleSystem::new(), "rumble_system", &["ball_system"])
+        .with(systems::FollowSystem::new(), "follow_system", &[])
+        .with(systems::LodSystem::new(), "lod_system", &["follow_system"])
+        .with(systems::DebugOverlaySystem::new(), "debug_overlay_system", &["terrain_stream_system"]);
+    let mut game = CoreApplication::<_, gilrs::Event, PadEventReader>::new(assets_dir, Hybrid { terrain, save_course, export, record }, game_data)?;
     game.run();
 
     Ok(())
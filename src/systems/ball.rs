@@ -3,195 +3,120 @@ use amethyst::{
     core::timing::{Time},
     core::nalgebra::{
         base::{Unit},
-        Point, Point3, Isometry3, Vector3
+        Point, Point3
     },
     core::Transform,
-    renderer::{DebugLinesComponent, Rgba}
+    renderer::{DebugLinesComponent, Rgba},
+    shrev::EventChannel
 };
-use gilrs::{Event, Button::*, Axis::*};
-use gilrs::ev::EventType::*;
 use glm;
-use ncollide3d::query::{Ray, RayCast, PointQuery};
 
-use std::f32::consts::*;
 use std::time::Instant;
 
-use hybrid::Ball;
-use hybrid::Chunk;
+use hybrid::{surface_contact, Ball, PlayerInput, TickInput};
+use hybrid::ball::heading;
+use hybrid::physics::{self, BallParams, Contact, FixedStep};
+use hybrid::replay::Recorder;
+use hybrid::{Chunk, ChunkIndex, DebugOverlay};
+use hybrid::rumble::ImpactEvent;
 
+fn find_contact(index: &ChunkIndex, chunks: &ReadStorage<Chunk>, position: &glm::Vec3) -> Option<Contact> {
+    surface_contact(index.containing(&Point::from(*position)).filter_map(|entity| chunks.get(entity)), position)
+}
+
+/// Simulates every ball, steered by its PlayerInput if it has one.
 pub struct BallSystem {
-    pub velocity: glm::Vec3,
-    pub rotation: f32,
-    pub left_stick: glm::Vec2,
-    pub right_stick: glm::Vec2
+    // Shared by all balls, so they step together
+    step: FixedStep
 }
 
 impl BallSystem {
     pub fn new() -> Self {
         BallSystem {
-            velocity: glm::vec3(0.0, 0.0, 0.0),
-            rotation: 0.0,
-            left_stick: glm::vec2(0.0, 0.0),
-            right_stick: glm::vec2(0.0, 0.0)
+            step: FixedStep::default()
         }
     }
 }
 
-fn clamp(n: f32) -> f32 {
-    if n <= 0.0 {
-        0.0
-    } else if n >= 1.0 {
-        1.0
-    } else {
-        n
-    }
-}
-
 impl<'s> System<'s> for BallSystem {
     type SystemData = (
-        ReadStorage<'s, Ball>,
+        Entities<'s>,
+        WriteStorage<'s, Ball>,
+        WriteStorage<'s, PlayerInput>,
         ReadStorage<'s, Chunk>,
         WriteStorage<'s, Transform>,
         WriteStorage<'s, DebugLinesComponent>,
-        Write<'s, Vec<Event>>,
-        Read<'s, Time>
+        Read<'s, Time>,
+        Read<'s, ChunkIndex>,
+        Read<'s, DebugOverlay>,
+        Write<'s, EventChannel<ImpactEvent>>,
+        Write<'s, Recorder>
     );
 
     fn setup(&mut self, res: &mut Resources) {
         Self::SystemData::setup(res);
     }
 
-    fn run(&mut self, (balls, chunks, mut transforms, mut debuglines, mut events, time): Self::SystemData) {
+    fn run(&mut self, (entities, mut balls, mut inputs, chunks, mut transforms, mut debuglines, time, index, overlay, mut impacts, mut recorder): Self::SystemData) {
         let start = Instant::now();
 
-        for (_ball, mut transform, mut debugline) in (&balls, &mut transforms, &mut debuglines).join() {
+        let params = BallParams::default();
+        let up = glm::vec3(0.0, 1.0, 0.0);
+        let steps = self.step.advance(time.delta_seconds());
+        let alpha = self.step.alpha();
+        let first_tick = recorder.tick;
+        recorder.tick += u64::from(steps);
+
+        for (entity, ball, input, transform, debugline) in (&*entities, &mut balls, (&mut inputs).maybe(), &mut transforms, &mut debuglines).join() {
             let start = Instant::now();
+            debugline.clear();
 
-            for event in events.drain(..) {
-                match event {
-                    Event { id: _, event: ButtonPressed(South, _), time: _ } => {
-                        transform.translate_x(-1.0);
-                    },
-                    Event { id: _, event: AxisChanged(LeftStickX, x, _), time: _ } =>
-                        self.left_stick.x = x,
-                    Event { id: _, event: AxisChanged(LeftStickY, y, _), time: _ } =>
-                        self.left_stick.y = y,
-                    Event { id: _, event: AxisChanged(RightStickX, x, _), time: _ } =>
-                        self.right_stick.x = x,
-                    Event { id: _, event: AxisChanged(RightStickY, y, _), time: _ } =>
-                        self.right_stick.y = y,
-
-                    _ => ()
-                }
-            };
+            let mut tick_input = input.as_ref().map_or(TickInput::default(), |input| input.tick_input());
+            let player = input.as_ref().map(|input| input.player);
 
-            let elapsed = start.elapsed();
-            println!("* Input: {:?}", elapsed);
-
-            let start = Instant::now();
+            ball.frame(&mut tick_input, steps, &params, |position| find_contact(&index, &chunks, position), |step, ball, tick_input, impact| {
+                if let Some((kind, speed)) = impact {
+                    impacts.single_write(ImpactEvent { ball: entity, kind, speed });
+                };
 
-            let mut intersection_point = None;
-
-            for chunk in (&chunks).join() {
-                let point = Point::from(*transform.translation());
-
-                if chunk.bounding_box.contains_point(&Isometry3::identity(), &point) {
-                    // We find our intersection point with the bezier surface by first raycasting down, and if that fails raycast up.
-                    for direction in [-1.0, 1.0].iter() {
-                        let ray = Ray {
-                            origin: point,
-                            dir: glm::vec3(0.0, *direction, 0.0)
-                        };
-
-                        match chunk.collision_mesh.toi_and_normal_and_uv_with_ray(&Isometry3::identity(), &ray, false) {
-                            Some(hit) => {
-                                let uv = hit.uvs.unwrap();
-                                const BEZIER_SMOOTHNESS: i32 = 256; // Higher = smoother
-                                let normal = chunk.patch.normal(BEZIER_SMOOTHNESS, clamp(uv.x), clamp(uv.y));
-
-                                intersection_point = Some((ray.origin + ray.dir * hit.toi, normal, direction));
-                                break
-                            }
-                            None => ()
-                        }
-                    }
+                if let (Some(player), Some(recording)) = (player, recorder.recording.as_mut()) {
+                    recording.record(first_tick + u64::from(step), player, &ball.spawn, tick_input, &ball.current.position);
                 };
-                if intersection_point.is_some() { break };
+            });
+
+            if let Some(input) = input {
+                // Only wait for a step to jump or reset on, a jump in the air is dropped
+                input.jump = tick_input.jump;
+                input.reset = tick_input.reset;
             };
 
             let elapsed = start.elapsed();
-            // debug format:
-            println!("* Collision: {:?}", elapsed);
+            println!("* Collision and physics: {:?}", elapsed);
 
-            let start = Instant::now();
+            let position = physics::interpolate(&ball.previous, &ball.current, alpha);
+            transform.set_position(position);
 
-            const SPEED: f32 = 20.0;
-            const MASS: f32 = 80.0;
-            const DRAG_COEFFICIENT: f32 = 1.0;
-
-            let gravity = glm::vec3(0.0, -0.0098, 0.0);
-            let up = glm::vec3(0.0, 1.0, 0.0);
-            let speed = self.velocity.magnitude();
-            let drag_scalar = DRAG_COEFFICIENT * (f32::powi(speed, 2) / 2.0);
-            let drag = if speed >= 0.001 { self.velocity.normalize() * -drag_scalar } else { glm::vec3(0.0, 0.0, 0.0) };
-
-            self.rotation += self.left_stick.x / 30.0;
-            self.rotation = if self.rotation >= 2.0 * PI { self.rotation - 2.0 * PI } else { self.rotation };
-            self.rotation = if self.rotation <  0.0 * PI { self.rotation + 2.0 * PI } else { self.rotation };
-
-            match intersection_point {
-                None => {
-                    // Player is not within any surface collision box, and free-falling
-                    let accel = (MASS * gravity + drag) / MASS;
-                    self.velocity += accel * time.delta_seconds();
-                    transform.translate(self.velocity);
-                },
-                Some((p, normal, _)) => {
-                    // How soft the surface is
-                    const SQUISHYNESS: f32 = 1.0;
-                    let height = transform.translation().y - p.y;
-                    if height >= 0.0 {
-                        let squish = if height <= SQUISHYNESS { f32::sin(height * PI / (SQUISHYNESS * 2.0)) } else { 1.0 };
-                        let accel = (MASS * gravity + drag) / MASS;
-                        self.velocity += accel * (squish * 0.01) * time.delta_seconds();
-                        transform.translate(self.velocity);
-                    };
-                    if transform.translation().y <= p.y {
-                        transform.set_y(p.y);
-                    }
-
-                    //transform.translation.x = p.x;
-                    //transform.translation.y = p.y;
-
-                    //debugline.clear();
-
-                    let angle = glm::rotate_vec3(&(up.cross(&normal)), -(0.5 * PI), &normal);
-                    let rotation = glm::quat_angle_axis(self.rotation, &normal);
-                    let dir = glm::quat_cross_vec(&rotation, &angle);
-                    //let dir2 = glm::rotate_vec3(&(dir.cross(&normal)), -(0.5 * PI), &normal);
-                    //let dir2 = if dir2.z >= 0.0 { dir2 * -1.0 } else { dir2 };
-
-                    transform.translate(dir * 0.2 * -dir.y);
-
-                    //debugline.add_direction(Point3::new(p.x, p.y + 2.0, p.z), angle * 2.0, Rgba::green());
-                    //debugline.add_direction(Point3::new(p.x, p.y + 2.0, p.z), angle * -2.0, Rgba::green());
-                    //debugline.add_direction(Point3::new(p.x, p.y + 2.0, p.z), dir * 2.0, Rgba::red());
-                    //debugline.add_direction(Point3::new(p.x, p.y + 2.0, p.z), dir * -2.0, Rgba::white());
-                    //debugline.add_direction(Point3::new(p.x, p.y, p.z), normal * 5.0, Rgba::blue());
-
-                    transform.look_at(Vector3::new(p.x, p.y, p.z) + dir, up);
-                }
-            }
-
-            transform.translate_x(self.right_stick.x * SPEED * time.delta_seconds());
-            transform.translate_z(-self.right_stick.y * SPEED * time.delta_seconds());
+            if let Some(Contact { point: p, normal }) = ball.contact {
+                let (dir, angle) = heading(&normal, ball.rotation);
 
-            let elapsed = start.elapsed();
-            println!("* Physics: {:?}", elapsed);
+                if overlay.enabled {
+                    debugline.add_direction(Point3::new(p.x, p.y + 2.0, p.z), angle * 2.0, Rgba::green());
+                    debugline.add_direction(Point3::new(p.x, p.y + 2.0, p.z), angle * -2.0, Rgba::green());
+                    debugline.add_direction(Point3::new(p.x, p.y + 2.0, p.z), dir * 2.0, Rgba::red());
+                    debugline.add_direction(Point3::new(p.x, p.y + 2.0, p.z), dir * -2.0, Rgba::white());
+                    debugline.add_direction(Point3::new(p.x, p.y, p.z), normal * 5.0, Rgba::blue());
+                    debugline.add_direction(Point3::from(position), ball.current.velocity, Rgba(1.0, 1.0, 0.0, 1.0));
+                };
+
+                // Looking straight up or down, or at the ball itself, has no rotation to turn to
+                if glm::length(&dir.cross(&up)) > 1e-6 {
+                    transform.look_at(position + dir, up);
+                };
+            };
         }
 
         let elapsed = start.elapsed();
         println!("Player movement system: {:?}", elapsed);
 
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,53 @@
+use amethyst::{
+    ecs::prelude::*,
+    shrev::ReaderId
+};
+
+use std::time::Instant;
+
+use hybrid::{Chunk, ChunkIndex};
+
+/// Keeps the ChunkIndex resource in sync as chunks are added and removed.
+pub struct ChunkIndexSystem {
+    reader: Option<ReaderId<ComponentEvent>>
+}
+
+impl ChunkIndexSystem {
+    pub fn new() -> Self {
+        ChunkIndexSystem { reader: None }
+    }
+}
+
+impl<'s> System<'s> for ChunkIndexSystem {
+    type SystemData = (
+        Entities<'s>,
+        ReadStorage<'s, Chunk>,
+        Write<'s, ChunkIndex>
+    );
+
+    fn setup(&mut self, res: &mut Resources) {
+        Self::SystemData::setup(res);
+        self.reader = Some(WriteStorage::<Chunk>::fetch(res).register_reader());
+    }
+
+    fn run(&mut self, (entities, chunks, mut index): Self::SystemData) {
+        let start = Instant::now();
+
+        for event in chunks.channel().read(self.reader.as_mut().unwrap()) {
+            match *event {
+                ComponentEvent::Inserted(id) | ComponentEvent::Modified(id) => {
+                    let entity = entities.entity(id);
+                    if let Some(chunk) = chunks.get(entity) {
+                        index.insert(entity, chunk.bounding_box.clone());
+                    }
+                },
+                ComponentEvent::Removed(id) => {
+                    index.remove(entities.entity(id));
+                }
+            }
+        };
+
+        let elapsed = start.elapsed();
+        println!("Chunk index system: {:?} ({} chunks)", elapsed, index.len());
+    }
+}
@@ -0,0 +1,111 @@
+use amethyst::{
+    ecs::prelude::*,
+    core::Transform,
+    core::nalgebra::Point3,
+    renderer::{DebugLinesComponent, Rgba}
+};
+
+use std::collections::HashSet;
+use std::time::Instant;
+
+use hybrid::{draw_control_plane, world_point, Ball, Chunk, DebugOverlay, CHUNK_SCALE};
+use hybrid::terrain::Terrain;
+
+// Only chunks this close to a ball are drawn, drawing them all would swamp the renderer
+const OVERLAY_DISTANCE: i32 = 4;
+
+/// Draws control planes with all eight of their handle points, the control net of each patch and
+/// chunk bounding boxes around the balls while the DebugOverlay is enabled.
+pub struct DebugOverlaySystem {
+    lines: Option<Entity>
+}
+
+impl DebugOverlaySystem {
+    pub fn new() -> Self {
+        DebugOverlaySystem { lines: None }
+    }
+}
+
+impl<'s> System<'s> for DebugOverlaySystem {
+    type SystemData = (
+        Entities<'s>,
+        ReadStorage<'s, Ball>,
+        ReadStorage<'s, Chunk>,
+        ReadStorage<'s, Transform>,
+        WriteStorage<'s, DebugLinesComponent>,
+        ReadExpect<'s, Terrain>,
+        Read<'s, DebugOverlay>
+    );
+
+    fn setup(&mut self, res: &mut Resources) {
+        Self::SystemData::setup(res);
+    }
+
+    fn run(&mut self, (entities, balls, chunks, transforms, mut debuglines, terrain, overlay): Self::SystemData) {
+        let start = Instant::now();
+
+        let entity = match self.lines {
+            Some(entity) if entities.is_alive(entity) => entity,
+            _ => {
+                let entity = entities.create();
+                debuglines.insert(entity, DebugLinesComponent::new()).expect("Overlay entity is alive");
+                self.lines = Some(entity);
+                entity
+            }
+        };
+        let lines = debuglines.get_mut(entity).expect("Overlay has debug lines");
+        lines.clear();
+
+        if !overlay.enabled {
+            return
+        };
+
+        let centers: Vec<(i32, i32)> = (&balls, &transforms).join()
+            .map(|(_ball, transform)| {
+                let p = transform.translation();
+                ((p.x / CHUNK_SCALE).floor() as i32, (p.z / CHUNK_SCALE).floor() as i32)
+            })
+            .collect();
+        let near = |(i, j): (i32, i32)| centers.iter().any(|&(ci, cj)| (i - ci).abs() <= OVERLAY_DISTANCE && (j - cj).abs() <= OVERLAY_DISTANCE);
+
+        // Control planes at the corners of the drawn chunks, including those along the far border
+        let mut planes = HashSet::new();
+
+        for chunk in (&chunks).join().filter(|chunk| near(chunk.coord)) {
+            // Control net, along both directions
+            let net = chunk.patch.control_net();
+            for a in 0..4 {
+                for b in 0..3 {
+                    lines.add_line(world_point(net[a][b]), world_point(net[a][b + 1]), Rgba(1.0, 0.6, 0.0, 1.0));
+                    lines.add_line(world_point(net[b][a]), world_point(net[b + 1][a]), Rgba(1.0, 0.6, 0.0, 1.0));
+                }
+            };
+
+            // Bounding box
+            let (mins, maxs) = (chunk.bounding_box.mins(), chunk.bounding_box.maxs());
+            let corner = |k: usize| Point3::new(
+                if k & 1 == 0 { mins.x } else { maxs.x },
+                if k & 2 == 0 { mins.y } else { maxs.y },
+                if k & 4 == 0 { mins.z } else { maxs.z }
+            );
+            for k in 0..8 {
+                // Connect each corner to the corners differing in one axis
+                for bit in [1, 2, 4].iter() {
+                    if k & bit == 0 {
+                        lines.add_line(corner(k), corner(k | bit), Rgba(0.3, 0.3, 0.3, 1.0));
+                    }
+                }
+            };
+
+            let (i, j) = chunk.coord;
+            planes.extend([(i, j), (i + 1, j), (i, j + 1), (i + 1, j + 1)].iter().cloned());
+        };
+
+        for plane in planes.iter().filter_map(|&(i, j)| terrain.source.plane(i, j)) {
+            draw_control_plane(lines, &plane, None);
+        };
+
+        let elapsed = start.elapsed();
+        println!("Debug overlay system: {:?}", elapsed);
+    }
+}
@@ -13,18 +13,25 @@ use std::time::Instant;
 
 use hybrid::Follow;
 
-pub struct FollowSystem {
-    target: Option<Entity>
-}
+// Distance and height the camera keeps from a lone ball
+const FOLLOW_DISTANCE: f32 = 35.0;
+const FOLLOW_HEIGHT: f32 = 15.0;
+// Distance needed per unit of spread between the balls to keep them all in the 60 degree field of view, with a margin
+const FRAMING: f32 = 2.0;
+const SPEED: f32 = 10.0;
+
+/// Moves every camera after the balls it follows, backing away as they spread out so they all stay in view.
+pub struct FollowSystem;
 
 impl FollowSystem {
     pub fn new() -> Self {
-        FollowSystem { target: None }
+        FollowSystem
     }
 }
 
 impl<'s> System<'s> for FollowSystem {
     type SystemData = (
+        Entities<'s>,
         ReadStorage<'s, Follow>,
         ReadStorage<'s, Camera>,
         WriteStorage<'s, Transform>,
@@ -35,34 +42,40 @@ impl<'s> System<'s> for FollowSystem {
         Self::SystemData::setup(res);
     }
 
-    fn run(&mut self, (followers, cameras, mut transforms, time): Self::SystemData) {
+    fn run(&mut self, (entities, followers, cameras, mut transforms, time): Self::SystemData) {
         let start = Instant::now();
 
-        let point = match self.target {
-            None => Vector3::new(0.0, 0.0, 0.0),
-            Some(target) => {
-                *transforms.get(target).unwrap().translation()
-            }
-        };
+        // The middle of each camera's balls, and how far the furthest is from it
+        let targets: Vec<(Entity, Vector3<f32>, f32)> = (&*entities, &followers, &cameras).join()
+            .filter_map(|(entity, follow, _camera)| {
+                let points: Vec<Vector3<f32>> = follow.entities.iter()
+                    .filter_map(|target| transforms.get(*target).map(|target| *target.translation()))
+                    .collect();
+                if points.is_empty() {
+                    return None
+                };
+                let center = points.iter().fold(Vector3::zeros(), |sum, point| sum + point) / points.len() as f32;
+                let spread = points.iter().map(|point| (point - center).magnitude()).fold(0.0, f32::max);
+                Some((entity, center, spread))
+            })
+            .collect();
 
-        for (follow, _camera, mut transform) in (&followers, &cameras, &mut transforms).join() {
-            self.target = Some(follow.entity); // Won't take effect until next frame
+        for (entity, point, spread) in targets {
+            let transform = transforms.get_mut(entity).unwrap();
 
-            const SPEED: f32 = 10.0;
+            let reach = spread * FRAMING;
+            let distance = FOLLOW_DISTANCE.max(reach);
             let dir = point - transform.translation();
-            if dir.magnitude() > 35.0 {
+            if dir.magnitude() > distance {
                 transform.translate(dir.normalize() * SPEED * time.delta_seconds());
-            }
-            transform.set_y(point.y + 15.0);
-
-            let dir = {
-                let eye = transform.translation();
-                (point - eye).normalize()
+            } else if dir.magnitude() < reach {
+                transform.translate(dir.normalize() * -SPEED * time.delta_seconds());
             };
+            transform.set_y(point.y + FOLLOW_HEIGHT * distance / FOLLOW_DISTANCE);
 
             transform.look_at(point, Vector3::new(0.0, 1.0, 0.0));
         }
         let elapsed = start.elapsed();
         println!("Camera movement system: {:?}", elapsed);
     }
-}
\ No newline at end of file
+}
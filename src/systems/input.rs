@@ -0,0 +1,121 @@
+use amethyst::{
+    ecs::prelude::*,
+    input::InputHandler
+};
+use glm;
+
+use std::collections::HashMap;
+use std::time::Instant;
+
+use hybrid::PlayerInput;
+use hybrid::controls::{ActionMap, AxisBinding, ButtonBinding};
+use hybrid::gamepad::{Gamepads, PadState};
+
+// What a player's bindings can read this frame
+struct Devices<'a> {
+    pad: Option<&'a PadState>,
+    // Only player one has the keyboard and mouse
+    keyboard: Option<&'a InputHandler<String, String>>,
+    mouse_dx: f32
+}
+
+impl<'a> Devices<'a> {
+    fn axis(&self, bindings: &[AxisBinding]) -> f32 {
+        let value: f32 = bindings.iter()
+            .map(|binding| match (binding, self.pad, self.keyboard) {
+                (AxisBinding::Pad(axis, settings), Some(pad), _) =>
+                    settings.apply(pad.axis(*axis)),
+                (AxisBinding::PadButtons { negative, positive }, Some(pad), _) =>
+                    direction(pad.button(*negative), pad.button(*positive)),
+                (AxisBinding::Keys { negative, positive }, _, Some(keyboard)) =>
+                    direction(keyboard.key_is_down(*negative), keyboard.key_is_down(*positive)),
+                (AxisBinding::MouseDrag { button, pixels, settings }, _, Some(keyboard)) if keyboard.mouse_button_is_down(*button) =>
+                    settings.apply((self.mouse_dx / pixels).max(-1.0).min(1.0)),
+                _ => 0.0
+            })
+            .sum();
+        value.max(-1.0).min(1.0)
+    }
+
+    fn button(&self, bindings: &[ButtonBinding]) -> bool {
+        bindings.iter().any(|binding| match (binding, self.pad, self.keyboard) {
+            (ButtonBinding::Pad(button), Some(pad), _) => pad.button(*button),
+            (ButtonBinding::Key(key), _, Some(keyboard)) => keyboard.key_is_down(*key),
+            (ButtonBinding::Mouse(button), _, Some(keyboard)) => keyboard.mouse_button_is_down(*button),
+            _ => false
+        })
+    }
+}
+
+fn direction(negative: bool, positive: bool) -> f32 {
+    match (negative, positive) {
+        (true, false) => -1.0,
+        (false, true) => 1.0,
+        _ => 0.0
+    }
+}
+
+/// Turns the state of the pads and the keyboard and mouse into each player's PlayerInput, through the ActionMap.
+/// The keyboard and mouse belong to player one, so the game can be played without a pad.
+pub struct PlayerInputSystem {
+    actions: ActionMap,
+    mouse_x: Option<f64>,
+    // Whether each player held jump and reset last frame, so holding them only triggers once
+    held: HashMap<usize, (bool, bool)>
+}
+
+impl PlayerInputSystem {
+    pub fn new(actions: ActionMap) -> Self {
+        PlayerInputSystem {
+            actions,
+            mouse_x: None,
+            held: HashMap::new()
+        }
+    }
+}
+
+impl<'s> System<'s> for PlayerInputSystem {
+    type SystemData = (
+        WriteStorage<'s, PlayerInput>,
+        Read<'s, Gamepads>,
+        Read<'s, InputHandler<String, String>>
+    );
+
+    fn setup(&mut self, res: &mut Resources) {
+        Self::SystemData::setup(res);
+    }
+
+    fn run(&mut self, (mut inputs, gamepads, keyboard): Self::SystemData) {
+        let start = Instant::now();
+
+        let mouse_dx = match (keyboard.mouse_position(), self.mouse_x) {
+            (Some((x, _)), Some(last_x)) => (x - last_x) as f32,
+            _ => 0.0
+        };
+        self.mouse_x = keyboard.mouse_position().map(|(x, _)| x);
+
+        for input in (&mut inputs).join() {
+            let devices = Devices {
+                pad: input.pad.and_then(|pad| gamepads.pad(pad)),
+                keyboard: if input.player == 0 { Some(&*keyboard) } else { None },
+                mouse_dx
+            };
+
+            input.steer = devices.axis(&self.actions.steer);
+            input.lean = devices.axis(&self.actions.lean);
+            let push = glm::vec2(devices.axis(&self.actions.push_x), devices.axis(&self.actions.push_y));
+            input.push = if glm::length(&push) > 1.0 { glm::normalize(&push) } else { push };
+            input.brake = devices.button(&self.actions.brake);
+
+            // Presses are kept until the ball acts on them, so a press between physics steps isn't lost
+            let (jump, reset) = (devices.button(&self.actions.jump), devices.button(&self.actions.reset));
+            let held = self.held.entry(input.player).or_insert((false, false));
+            input.jump = input.jump || (jump && !held.0);
+            input.reset = input.reset || (reset && !held.1);
+            *held = (jump, reset);
+        };
+
+        let elapsed = start.elapsed();
+        println!("Player input system: {:?}", elapsed);
+    }
+}
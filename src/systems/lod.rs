@@ -0,0 +1,83 @@
+use amethyst::{
+    ecs::prelude::*,
+    assets::{AssetStorage, Loader},
+    core::Transform,
+    renderer::{Camera, Mesh, MeshHandle}
+};
+
+use std::collections::HashMap;
+use std::time::Instant;
+
+use hybrid::{Chunk, Lod};
+use hybrid::terrain::{eye_distance, lod_resolution};
+
+/// Retessellates chunks based on their distance to the nearest camera, stitching edges to coarser neighbours.
+pub struct LodSystem;
+
+impl LodSystem {
+    pub fn new() -> Self {
+        LodSystem
+    }
+}
+
+impl<'s> System<'s> for LodSystem {
+    type SystemData = (
+        Entities<'s>,
+        ReadStorage<'s, Chunk>,
+        WriteStorage<'s, Lod>,
+        WriteStorage<'s, MeshHandle>,
+        ReadStorage<'s, Camera>,
+        ReadStorage<'s, Transform>,
+        ReadExpect<'s, Loader>,
+        Read<'s, AssetStorage<Mesh>>
+    );
+
+    fn setup(&mut self, res: &mut Resources) {
+        Self::SystemData::setup(res);
+    }
+
+    fn run(&mut self, (entities, chunks, mut lods, mut meshes, cameras, transforms, loader, mesh_storage): Self::SystemData) {
+        let start = Instant::now();
+
+        // Each chunk is as detailed as the closest camera needs
+        let eyes: Vec<_> = (&cameras, &transforms).join()
+            .map(|(_camera, transform)| *transform.translation())
+            .collect();
+        if eyes.is_empty() {
+            return
+        };
+
+        let levels: HashMap<(i32, i32), i32> = (&chunks).join()
+            .map(|chunk| {
+                let center = chunk.bounding_box.center().coords;
+                (chunk.coord, lod_resolution(eye_distance(&center, &eyes)))
+            })
+            .collect();
+
+        let mut rebuilt = 0;
+        for (entity, chunk, lod) in (&*entities, &chunks, &mut lods).join() {
+            let (i, j) = chunk.coord;
+            let res = levels[&chunk.coord];
+
+            // North, east, south and west neighbours
+            let mut edges = [res; 4];
+            for (edge, neighbour) in edges.iter_mut().zip([(i, j + 1), (i + 1, j), (i, j - 1), (i - 1, j)].iter()) {
+                if let Some(neighbour_res) = levels.get(neighbour) {
+                    *edge = res.min(*neighbour_res);
+                }
+            };
+
+            if lod.res != res || lod.edges != edges {
+                let mesh = loader.load_from_data(chunk.patch.rasterize_stitched(res, edges).into(), (), &mesh_storage);
+                meshes.insert(entity, mesh).expect("Chunk entity is alive");
+
+                lod.res = res;
+                lod.edges = edges;
+                rebuilt += 1;
+            }
+        };
+
+        let elapsed = start.elapsed();
+        println!("LOD system: {:?} ({} chunks rebuilt)", elapsed, rebuilt);
+    }
+}
@@ -1,8 +1,19 @@
-
 mod ball;
+mod chunk_index;
+mod debug_overlay;
 mod follow;
+mod input;
+mod lod;
+mod rumble;
+mod terrain_stream;
 
 pub use self::{
     ball::BallSystem,
-    follow::FollowSystem
-};
\ No newline at end of file
+    chunk_index::ChunkIndexSystem,
+    debug_overlay::DebugOverlaySystem,
+    follow::FollowSystem,
+    input::PlayerInputSystem,
+    lod::LodSystem,
+    rumble::RumbleSystem,
+    terrain_stream::TerrainStreamSystem
+};
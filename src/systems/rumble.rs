@@ -0,0 +1,49 @@
+use amethyst::{
+    ecs::prelude::*,
+    shrev::{EventChannel, ReaderId}
+};
+
+use std::time::Instant;
+
+use hybrid::PlayerInput;
+use hybrid::gamepad::Gamepads;
+use hybrid::rumble::{self, ImpactEvent};
+
+/// Rumbles the pad of the player whose ball hit the terrain.
+pub struct RumbleSystem {
+    reader: Option<ReaderId<ImpactEvent>>
+}
+
+impl RumbleSystem {
+    pub fn new() -> Self {
+        RumbleSystem { reader: None }
+    }
+}
+
+impl<'s> System<'s> for RumbleSystem {
+    type SystemData = (
+        ReadStorage<'s, PlayerInput>,
+        Read<'s, EventChannel<ImpactEvent>>,
+        Write<'s, Gamepads>
+    );
+
+    fn setup(&mut self, res: &mut Resources) {
+        Self::SystemData::setup(res);
+        self.reader = Some(res.fetch_mut::<EventChannel<ImpactEvent>>().register_reader());
+    }
+
+    fn run(&mut self, (inputs, impacts, mut gamepads): Self::SystemData) {
+        let start = Instant::now();
+
+        for impact in impacts.read(self.reader.as_mut().unwrap()) {
+            let pad = match inputs.get(impact.ball).and_then(|input| input.pad) {
+                Some(pad) => pad,
+                None => continue
+            };
+            gamepads.rumble(pad, rumble::effect_for_impact(impact.kind, impact.speed));
+        };
+
+        let elapsed = start.elapsed();
+        println!("Rumble system: {:?}", elapsed);
+    }
+}
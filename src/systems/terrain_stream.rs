@@ -0,0 +1,145 @@
+use amethyst::{
+    ecs::prelude::*,
+    assets::{AssetStorage, Loader},
+    core::Transform,
+    renderer::{Camera, Mesh, MeshHandle, TriplanarMaterial}
+};
+
+use std::collections::{HashMap, HashSet};
+use std::time::Instant;
+
+use hybrid::{Ball, Chunk, Lod, CHUNK_SCALE};
+use hybrid::ControlSurface;
+use hybrid::terrain::{generate_chunks, ChunkGeometry, Terrain, TerrainMaterials};
+
+// Chunks are only unloaded this many chunks past the view distance, so they don't flicker at the border
+const HYSTERESIS: i32 = 2;
+// Cap on chunks generated per frame, nearest first, so moving into new terrain doesn't stall
+const MAX_SPAWNS: usize = 256;
+
+/// Spawns chunks in a ring around every ball, and despawns them once every ball has left them behind.
+pub struct TerrainStreamSystem {
+    loaded: HashMap<(i32, i32), Entity>
+}
+
+impl TerrainStreamSystem {
+    pub fn new() -> Self {
+        TerrainStreamSystem { loaded: HashMap::new() }
+    }
+}
+
+fn chunk_coord(x: f32, z: f32) -> (i32, i32) {
+    ((x / CHUNK_SCALE).floor() as i32, (z / CHUNK_SCALE).floor() as i32)
+}
+
+fn distance_squared((i, j): (i32, i32), (ci, cj): (i32, i32)) -> i32 {
+    (i - ci) * (i - ci) + (j - cj) * (j - cj)
+}
+
+impl<'s> System<'s> for TerrainStreamSystem {
+    type SystemData = (
+        Entities<'s>,
+        ReadStorage<'s, Ball>,
+        WriteStorage<'s, Transform>,
+        WriteStorage<'s, Chunk>,
+        WriteStorage<'s, Lod>,
+        WriteStorage<'s, MeshHandle>,
+        WriteStorage<'s, TriplanarMaterial>,
+        ReadStorage<'s, Camera>,
+        WriteExpect<'s, Terrain>,
+        ReadExpect<'s, TerrainMaterials>,
+        ReadExpect<'s, Loader>,
+        Read<'s, AssetStorage<Mesh>>,
+        Read<'s, AssetStorage<ControlSurface>>
+    );
+
+    fn setup(&mut self, res: &mut Resources) {
+        Self::SystemData::setup(res);
+    }
+
+    fn run(&mut self, (entities, balls, mut transforms, mut chunks, mut lods, mut meshes, mut materials, cameras, mut terrain, terrain_materials, loader, mesh_storage, surfaces): Self::SystemData) {
+        let start = Instant::now();
+
+        terrain.source.resolve(&surfaces);
+
+        // New meshes are tessellated on the worker threads for the cameras, so the LodSystem doesn't redo them
+        let eyes: Vec<_> = (&cameras, &transforms).join()
+            .map(|(_camera, transform)| *transform.translation())
+            .collect();
+
+        // Regenerate chunks whose control planes were edited, in place
+        let mut dirty: Vec<(i32, i32)> = terrain.dirty.drain(..)
+            .filter(|coord| self.loaded.contains_key(coord))
+            .collect();
+        dirty.sort();
+        dirty.dedup();
+        for ChunkGeometry { chunk, vertices, lod } in generate_chunks(&terrain.source, &dirty, &eyes) {
+            let entity = self.loaded[&chunk.coord];
+            let mesh = loader.load_from_data(vertices.into(), (), &mesh_storage);
+            meshes.insert(entity, mesh).expect("Chunk entity is alive");
+            chunks.insert(entity, chunk).expect("Chunk entity is alive");
+            lods.insert(entity, lod).expect("Chunk entity is alive");
+        };
+
+        let centers: Vec<(i32, i32)> = (&balls, &transforms).join()
+            .map(|(_ball, transform)| chunk_coord(transform.translation().x, transform.translation().z))
+            .collect();
+
+        let near = |coord: (i32, i32), distance: i32| {
+            centers.iter().any(|center| distance_squared(coord, *center) <= distance * distance)
+        };
+
+        // Despawn chunks no ball is near any more
+        let view_distance = terrain.view_distance;
+        let far: Vec<(i32, i32)> = self.loaded.keys()
+            .filter(|coord| !near(**coord, view_distance + HYSTERESIS))
+            .cloned()
+            .collect();
+        for coord in far.iter() {
+            if let Some(entity) = self.loaded.remove(coord) {
+                entities.delete(entity).expect("Chunk entity is alive");
+            }
+        };
+
+        // Find missing chunks in range of a ball, nearest first
+        let mut missing = HashSet::new();
+        for center in centers.iter() {
+            for i in (center.0 - view_distance)..(center.0 + view_distance + 1) {
+                for j in (center.1 - view_distance)..(center.1 + view_distance + 1) {
+                    let coord = (i, j);
+                    if distance_squared(coord, *center) <= view_distance * view_distance
+                        && !self.loaded.contains_key(&coord)
+                        && terrain.source.contains(i, j) {
+                        missing.insert(coord);
+                    }
+                }
+            }
+        };
+        let mut missing: Vec<(i32, i32)> = missing.into_iter().collect();
+        missing.sort_by_key(|coord| centers.iter().map(|center| distance_squared(*coord, *center)).min());
+        missing.truncate(MAX_SPAWNS);
+
+        let spawned = generate_chunks(&terrain.source, &missing, &eyes);
+        let count = spawned.len();
+
+        for ChunkGeometry { chunk, vertices, lod } in spawned {
+            let mesh = loader.load_from_data(vertices.into(), (), &mesh_storage);
+
+            let mut trans = Transform::default();
+            trans.set_scale(CHUNK_SCALE, CHUNK_SCALE, CHUNK_SCALE);
+
+            let coord = chunk.coord;
+            let entity = entities.build_entity()
+                .with(mesh, &mut meshes)
+                .with(terrain_materials.triplanar(), &mut materials)
+                .with(trans, &mut transforms)
+                .with(chunk, &mut chunks)
+                .with(lod, &mut lods)
+                .build();
+            self.loaded.insert(coord, entity);
+        };
+
+        let elapsed = start.elapsed();
+        println!("Terrain stream system: {:?} ({} spawned, {} despawned, {} loaded)", elapsed, count, far.len(), self.loaded.len());
+    }
+}
@@ -0,0 +1,53 @@
+// Replays the recordings in tests/fixtures and checks the balls follow their golden trajectories.
+//
+// flat.recording.ron is two players on a seeded course with every height set to 0: one settling,
+// leaning and pushing East, then braking, the other joining later, jumping and pushing South East.
+extern crate hybrid;
+extern crate nalgebra_glm as glm;
+
+use hybrid::hybrid::TickInput;
+use hybrid::hybrid::replay::{self, Recording, Trajectory, TOLERANCE};
+
+fn fixture(name: &str) -> String {
+    format!("{}/tests/fixtures/{}", env!("CARGO_MANIFEST_DIR"), name)
+}
+
+fn load() -> (Recording, Trajectory) {
+    let recording = Recording::load(fixture("flat.recording.ron")).expect("Recording loads");
+    let golden = Trajectory::load(fixture("flat.golden.ron")).expect("Golden trajectory loads");
+    (recording, golden)
+}
+
+#[test]
+fn replay_matches_golden_trajectory() {
+    let (recording, golden) = load();
+    let trajectory = replay::replay(&recording);
+
+    trajectory.compare(&golden, TOLERANCE).unwrap();
+    trajectory.compare(&recording.trajectory(), TOLERANCE).unwrap();
+}
+
+#[test]
+fn changed_input_diverges_from_golden_trajectory() {
+    let (mut recording, golden) = load();
+    // Push South instead of East for a single step while player one is rolling
+    recording.players[0].inputs[100].push = [0.0, 1.0];
+
+    assert!(replay::replay(&recording).compare(&golden, TOLERANCE).is_err());
+}
+
+#[test]
+fn players_are_recorded_by_number() {
+    let (recording, _) = load();
+    let mut copy = Recording::new(recording.terrain.clone());
+    let spawn = glm::vec3(0.0, 1.0, 0.0);
+    let input = TickInput::default();
+
+    // Player two's first step comes before player one's
+    copy.record(0, 1, &spawn, &input, &spawn);
+    copy.record(0, 0, &spawn, &input, &spawn);
+    copy.record(1, 1, &spawn, &input, &spawn);
+
+    let players: Vec<(usize, usize)> = copy.players.iter().map(|player| (player.player, player.inputs.len())).collect();
+    assert_eq!(players, vec![(1, 2), (0, 1)]);
+}